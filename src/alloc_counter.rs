@@ -0,0 +1,202 @@
+//! A global allocator that counts allocations, installed with the `alloc-counter` feature.
+//!
+//! It only counts while [`measure`] runs. Otherwise it checks one flag and forwards to the
+//! system allocator, so timed code outside of `measure` doesn't pay for the bookkeeping.
+
+#[cfg(feature = "alloc-counter")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+
+use serde::Serialize;
+
+/// True if the counting allocator is installed and `measure` reports numbers.
+pub const ENABLED: bool = cfg!(feature = "alloc-counter");
+
+/// Set while [`measure`] runs, the counters below are only updated meanwhile.
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// Bytes live on top of what was live when counting started. Negative if more was freed.
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+static PEAK_LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+/// Allocations that would take the live bytes above this fail, see [`set_limit`].
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Forwards to the system allocator and records every allocation.
+#[cfg(feature = "alloc-counter")]
+pub struct CountingAllocator;
+
+#[cfg(feature = "alloc-counter")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !counting() {
+            return System.alloc(layout);
+        }
+        if exceeds_limit(layout.size()) {
+            return std::ptr::null_mut();
+        }
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if !counting() {
+            return System.alloc_zeroed(layout);
+        }
+        if exceeds_limit(layout.size()) {
+            return std::ptr::null_mut();
+        }
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        if counting() {
+            LIVE_BYTES.fetch_sub(as_isize(layout.size()), Ordering::Relaxed);
+        }
+    }
+
+    /// A realloc counts as a new allocation of `new_size` bytes.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !counting() {
+            return System.realloc(ptr, layout, new_size);
+        }
+        if exceeds_limit(new_size.saturating_sub(layout.size())) {
+            return std::ptr::null_mut();
+        }
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            LIVE_BYTES.fetch_sub(as_isize(layout.size()), Ordering::Relaxed);
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(feature = "alloc-counter")]
+fn counting() -> bool {
+    COUNTING.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "alloc-counter")]
+fn as_isize(size: usize) -> isize {
+    isize::try_from(size).unwrap_or(isize::MAX)
+}
+
+#[cfg(feature = "alloc-counter")]
+fn exceeds_limit(size: usize) -> bool {
+    let live = usize::try_from(LIVE_BYTES.load(Ordering::Relaxed)).unwrap_or(0);
+    live.saturating_add(size) > LIMIT.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "alloc-counter")]
+fn record_alloc(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size, Ordering::Relaxed);
+    let live = LIVE_BYTES.fetch_add(as_isize(size), Ordering::Relaxed) + as_isize(size);
+    PEAK_LIVE_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+/// Makes allocations in [`measure`] fail once `bytes` more than at its start would be live, like
+/// on a machine with that much memory. Rust aborts on a failed allocation. Only enforced if the
+/// allocator is installed.
+pub fn set_limit(bytes: usize) {
+    LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+/// Allocations made while running one phase.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct AllocStats {
+    /// Number of allocations, including reallocations.
+    pub allocations: usize,
+    /// Sum of the sizes of all allocations.
+    pub allocated_bytes: usize,
+    /// Highest number of bytes live at once, on top of what was live before the phase.
+    pub peak_live_bytes: usize,
+}
+
+/// Runs `f` and returns the allocations it made, or `None` if the allocator is not installed.
+///
+/// The counters are global, so other threads allocating at the same time are counted too.
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, Option<AllocStats>) {
+    if !ENABLED {
+        return (f(), None);
+    }
+    ALLOCATIONS.store(0, Ordering::Relaxed);
+    ALLOCATED_BYTES.store(0, Ordering::Relaxed);
+    LIVE_BYTES.store(0, Ordering::Relaxed);
+    PEAK_LIVE_BYTES.store(0, Ordering::Relaxed);
+    let res = {
+        let _counting = Counting::start();
+        f()
+    };
+
+    let stats = AllocStats {
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+        peak_live_bytes: PEAK_LIVE_BYTES.load(Ordering::Relaxed).max(0) as usize,
+    };
+    (res, Some(stats))
+}
+
+/// Counts until dropped, also if the measured function panics.
+struct Counting;
+
+impl Counting {
+    fn start() -> Self {
+        COUNTING.store(true, Ordering::SeqCst);
+        Counting
+    }
+}
+
+impl Drop for Counting {
+    fn drop(&mut self) {
+        COUNTING.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hint::black_box;
+
+    use super::*;
+
+    #[test]
+    fn counts_a_known_allocation() {
+        let (buf, stats) = measure(|| black_box(Vec::<u8>::with_capacity(1000)));
+        assert_eq!(buf.capacity(), 1000);
+        if !ENABLED {
+            assert!(stats.is_none());
+            return;
+        }
+        let stats = stats.unwrap();
+        // Tests run in parallel and the counters are global, so other tests can add to them.
+        assert!(stats.allocations >= 1);
+        assert!(stats.allocated_bytes >= 1000);
+        assert!(stats.peak_live_bytes >= 1000);
+    }
+
+    #[test]
+    fn counts_reallocations() {
+        let (_, stats) = measure(|| {
+            let mut buf = black_box(Vec::<u8>::with_capacity(10));
+            buf.reserve_exact(100);
+            buf
+        });
+        if !ENABLED {
+            assert!(stats.is_none());
+            return;
+        }
+        let stats = stats.unwrap();
+        assert!(stats.allocations >= 2);
+        assert!(stats.allocated_bytes >= 110);
+        assert!(stats.peak_live_bytes >= 100);
+    }
+}
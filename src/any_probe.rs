@@ -0,0 +1,366 @@
+//! Tells whether a type needs a self-describing format, because it calls `deserialize_any`, like
+//! `serde_json::Value` or untagged and internally tagged enums, or `deserialize_identifier` or
+//! `deserialize_ignored_any`. Other formats can't read those, but not all of them say so in their
+//! errors.
+
+use std::fmt;
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess,
+    SeqAccess, VariantAccess, Visitor,
+};
+
+/// Sequences, maps and options nested deeper than this are empty, so recursive types end.
+const MAX_DEPTH: usize = 8;
+
+/// The first method of a self-describing format that `T` calls, if any. `T` is read from made up
+/// data: zeros, empty strings, one element per sequence and map, `Some` and the first variant of
+/// each enum.
+pub fn self_describing_call<T: DeserializeOwned>() -> Option<&'static str> {
+    match T::deserialize(AnyProbe { depth: 0 }) {
+        Err(Probe::SelfDescribing(method)) => Some(method),
+        Err(Probe::Other) | Ok(_) => None,
+    }
+}
+
+/// Why [`AnyProbe`] stopped.
+#[derive(Debug)]
+enum Probe {
+    /// The type called this method of a self-describing format.
+    SelfDescribing(&'static str),
+    /// The type rejected the made up data.
+    Other,
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for Probe {}
+
+impl de::Error for Probe {
+    fn custom<M: fmt::Display>(_msg: M) -> Self {
+        Probe::Other
+    }
+}
+
+macro_rules! visit {
+    ($($method:ident($($arg:ty),*) => $visit:ident($($value:expr)?)),+ $(,)?) => {
+        $(fn $method<V: Visitor<'de>>(self, $(_: $arg,)* visitor: V) -> Result<V::Value, Probe> {
+            visitor.$visit($($value)?)
+        })+
+    };
+}
+
+/// Feeds the made up data to whatever the type asks for, and fails on the methods of
+/// self-describing formats.
+#[derive(Clone, Copy)]
+struct AnyProbe {
+    depth: usize,
+}
+
+impl AnyProbe {
+    /// The probe one level down, `None` once it is too deep.
+    fn nested(self) -> Option<AnyProbe> {
+        (self.depth < MAX_DEPTH).then_some(AnyProbe {
+            depth: self.depth + 1,
+        })
+    }
+
+    fn elements(self, len: usize) -> Elements {
+        Elements { probe: self, len }
+    }
+}
+
+impl<'de> Deserializer<'de> for AnyProbe {
+    type Error = Probe;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Probe> {
+        Err(Probe::SelfDescribing("deserialize_any"))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Probe> {
+        Err(Probe::SelfDescribing("deserialize_identifier"))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Probe> {
+        Err(Probe::SelfDescribing("deserialize_ignored_any"))
+    }
+
+    visit!(
+        deserialize_bool() => visit_bool(false),
+        deserialize_i8() => visit_i8(0),
+        deserialize_i16() => visit_i16(0),
+        deserialize_i32() => visit_i32(0),
+        deserialize_i64() => visit_i64(0),
+        deserialize_i128() => visit_i128(0),
+        deserialize_u8() => visit_u8(0),
+        deserialize_u16() => visit_u16(0),
+        deserialize_u32() => visit_u32(0),
+        deserialize_u64() => visit_u64(0),
+        deserialize_u128() => visit_u128(0),
+        deserialize_f32() => visit_f32(0.0),
+        deserialize_f64() => visit_f64(0.0),
+        deserialize_char() => visit_char('a'),
+        deserialize_str() => visit_str(""),
+        deserialize_string() => visit_str(""),
+        deserialize_bytes() => visit_bytes(&[]),
+        deserialize_byte_buf() => visit_bytes(&[]),
+        deserialize_unit() => visit_unit(),
+        deserialize_unit_struct(&'static str) => visit_unit(),
+    );
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Probe> {
+        match self.nested() {
+            Some(probe) => visitor.visit_some(probe),
+            None => visitor.visit_none(),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Probe> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Probe> {
+        match self.nested() {
+            Some(probe) => visitor.visit_seq(probe.elements(1)),
+            None => visitor.visit_seq(self.elements(0)),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Probe> {
+        visitor.visit_seq(self.elements(len))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Probe> {
+        visitor.visit_seq(self.elements(len))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Probe> {
+        match self.nested() {
+            Some(probe) => visitor.visit_map(Entries {
+                probe,
+                keys: Keys::Probed(1),
+            }),
+            None => visitor.visit_map(Entries {
+                probe: self,
+                keys: Keys::Probed(0),
+            }),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Probe> {
+        visitor.visit_map(Entries {
+            probe: self,
+            keys: Keys::Fields(fields),
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Probe> {
+        visitor.visit_enum(self)
+    }
+}
+
+/// `len` elements of a sequence or tuple.
+struct Elements {
+    probe: AnyProbe,
+    len: usize,
+}
+
+impl<'de> SeqAccess<'de> for Elements {
+    type Error = Probe;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Probe> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(self.probe).map(Some)
+    }
+}
+
+/// The keys of a map or struct.
+enum Keys {
+    /// As many made up keys.
+    Probed(usize),
+    /// The remaining fields of a struct.
+    Fields(&'static [&'static str]),
+}
+
+struct Entries {
+    probe: AnyProbe,
+    keys: Keys,
+}
+
+impl<'de> MapAccess<'de> for Entries {
+    type Error = Probe;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Probe> {
+        match &mut self.keys {
+            Keys::Probed(0) => Ok(None),
+            Keys::Probed(len) => {
+                *len -= 1;
+                seed.deserialize(self.probe).map(Some)
+            }
+            Keys::Fields(fields) => match fields.split_first() {
+                Some((field, rest)) => {
+                    *fields = rest;
+                    seed.deserialize(field.into_deserializer()).map(Some)
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Probe> {
+        seed.deserialize(self.probe)
+    }
+}
+
+/// Picks the first variant.
+impl<'de> EnumAccess<'de> for AnyProbe {
+    type Error = Probe;
+    type Variant = AnyProbe;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, AnyProbe), Probe> {
+        let variant = seed.deserialize(0u32.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for AnyProbe {
+    type Error = Probe;
+
+    fn unit_variant(self) -> Result<(), Probe> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Probe> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Probe> {
+        visitor.visit_seq(self.elements(len))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Probe> {
+        self.deserialize_struct("", fields, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    #[allow(dead_code)]
+    enum Untagged {
+        Int(i32),
+        Text(String),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(tag = "type")]
+    #[allow(dead_code)]
+    enum Tagged {
+        A { a: i32 },
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Nested {
+        id: u64,
+        names: Vec<String>,
+        values: HashMap<String, Option<Untagged>>,
+    }
+
+    /// Recursive, and only the last field needs `deserialize_any`.
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Tree {
+        children: Vec<Tree>,
+        parent: Option<Box<Tree>>,
+        value: serde_json::Value,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Plain {
+        Unit,
+        Struct { a: i32, b: Vec<(u8, char)> },
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct PlainTree {
+        children: Vec<PlainTree>,
+        kind: Plain,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(tag = "t", content = "c")]
+    #[allow(dead_code)]
+    enum Adjacent {
+        Unit,
+        A(i32),
+    }
+
+    #[test]
+    fn finds_self_describing_calls() {
+        let any = Some("deserialize_any");
+        assert_eq!(self_describing_call::<serde_json::Value>(), any);
+        assert_eq!(self_describing_call::<Untagged>(), any);
+        assert_eq!(self_describing_call::<Tagged>(), any);
+        assert_eq!(self_describing_call::<Vec<Untagged>>(), any);
+        assert_eq!(self_describing_call::<Option<serde_json::Value>>(), any);
+        assert_eq!(self_describing_call::<Nested>(), any);
+        assert_eq!(self_describing_call::<Tree>(), any);
+        // Unit variants read their content, if any, with `deserialize_any`.
+        assert_eq!(self_describing_call::<Vec<Adjacent>>(), any);
+    }
+
+    #[test]
+    fn passes_types_with_a_schema() {
+        assert_eq!(self_describing_call::<u64>(), None);
+        assert_eq!(self_describing_call::<Vec<String>>(), None);
+        assert_eq!(self_describing_call::<HashMap<String, u32>>(), None);
+        assert_eq!(self_describing_call::<Plain>(), None);
+        assert_eq!(self_describing_call::<PlainTree>(), None);
+    }
+}
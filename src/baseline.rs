@@ -0,0 +1,320 @@
+//! Saving results as a named baseline and comparing later runs against it.
+//!
+//! A baseline is the JSON export of a run, stored in `baselines/<name>.json`.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use prettytable::{cell, Row, Table};
+use serde::Deserialize;
+
+use crate::{
+    cli::OutputFormat, export, export::RunMetadata, first_line, get_table, stats::TimingStats,
+    FormatResult, ScenarioResult,
+};
+
+const BASELINE_DIR: &str = "baselines";
+
+fn baseline_path(name: &str) -> PathBuf {
+    PathBuf::from(BASELINE_DIR).join(format!("{name}.json"))
+}
+
+/// Parses a baseline name from the command line. Names are file names in `baselines/`, so path
+/// separators and `..`, which would leave the directory, are rejected.
+pub fn parse_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(format!(
+            "{name:?} is not a file name, baselines are stored in {BASELINE_DIR}/"
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// The parts of a JSON export needed for the comparison.
+#[derive(Debug, Deserialize)]
+pub struct Baseline {
+    pub metadata: RunMetadata,
+    results: BTreeMap<String, BTreeMap<String, BaselineResult>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BaselineResult {
+    result: String,
+    serialized_size: usize,
+    serialize_time: TimingStats,
+    deserialize_time: TimingStats,
+    roundtrip_time: TimingStats,
+}
+
+pub fn save(name: &str, metadata: &RunMetadata, scenarios: &[ScenarioResult]) -> Result<()> {
+    let path = baseline_path(name);
+    std::fs::create_dir_all(BASELINE_DIR)?;
+    let file =
+        File::create(&path).with_context(|| format!("could not create {}", path.display()))?;
+    export::write_json(BufWriter::new(file), metadata, scenarios)?;
+    eprintln!("Saved baseline {}", path.display());
+    Ok(())
+}
+
+pub fn load(name: &str) -> Result<Baseline> {
+    let path = baseline_path(name);
+    let file = File::open(&path).with_context(|| format!("could not open {}", path.display()))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("could not parse {}", path.display()))
+}
+
+/// Compares the results with the baseline and returns the comparison table and the number of
+/// regressions.
+///
+/// A format that worked in the baseline but fails now is a regression. A time is a regression if
+/// its median grew by more than `threshold` percent and the confidence intervals don't overlap.
+/// A size is a regression if it grew by more than `threshold` percent.
+pub fn compare(
+    baseline: &Baseline,
+    scenarios: &[ScenarioResult],
+    threshold: f64,
+    output: OutputFormat,
+) -> (Table, usize) {
+    let mut table = get_table(output);
+    let mut row = Row::empty();
+    row.add_cell(cell!["Scenario"]);
+    row.add_cell(cell!["Format"]);
+    row.add_cell(cell!["Serialized Size"]);
+    row.add_cell(cell!["Serialize Inlier Median"]);
+    row.add_cell(cell!["Deserialize Inlier Median"]);
+    row.add_cell(cell!["Roundtrip Inlier Median"]);
+    row.add_cell(cell!["Status"]);
+    table.set_titles(row);
+
+    let mut regressions = 0;
+    for scenario in scenarios {
+        for res in &scenario.results {
+            let base = baseline
+                .results
+                .get(&scenario.name)
+                .and_then(|formats| formats.get(&res.format));
+            let mut row = Row::empty();
+            row.add_cell(cell!(scenario.name));
+            row.add_cell(cell!(res.format));
+            let Some(base) = base.filter(|base| base.result == "Ok" && res.is_ok()) else {
+                for _ in 0..4 {
+                    row.add_cell(cell!(""));
+                }
+                let (status, regressed) = missing_status(base, res);
+                regressions += regressed as usize;
+                row.add_cell(cell!(status));
+                table.add_row(row);
+                continue;
+            };
+
+            let size_delta = delta_percent(base.serialized_size as f64, res.serialized_size as f64);
+            let mut regressed = size_delta > threshold;
+            row.add_cell(cell!(format!(
+                "{} -> {} ({:+.1}%)",
+                base.serialized_size, res.serialized_size, size_delta
+            )));
+            for (base, now) in [
+                (base.serialize_time, res.serialize_time),
+                (base.deserialize_time, res.deserialize_time),
+                (base.roundtrip_time, res.roundtrip_time),
+            ] {
+                let delta = delta_percent(base.median as f64, now.median as f64);
+                regressed |= delta > threshold && !base.overlaps(&now);
+                row.add_cell(cell!(format!("{:+.1}%", delta)));
+            }
+            if regressed {
+                regressions += 1;
+            }
+            row.add_cell(cell!(if regressed { "REGRESSION" } else { "ok" }));
+            table.add_row(row);
+        }
+    }
+    (table, regressions)
+}
+
+/// Status if there is nothing to compare, a format that stopped working is a regression.
+fn missing_status(base: Option<&BaselineResult>, res: &FormatResult) -> (String, bool) {
+    match base {
+        None => ("not in baseline".to_string(), false),
+        Some(base) if base.result != "Ok" => {
+            (format!("baseline: {}", first_line(&base.result)), false)
+        }
+        Some(_) => (format!("REGRESSION: {}", first_line(&res.result)), true),
+    }
+}
+
+fn delta_percent(base: f64, now: f64) -> f64 {
+    if base == 0.0 {
+        return 0.0;
+    }
+    (now - base) / base * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Timings with the median `median` and a confidence interval of ±1.
+    fn timing(median: u128) -> TimingStats {
+        TimingStats {
+            median,
+            ci_low: median.saturating_sub(1),
+            ci_high: median + 1,
+            ..Default::default()
+        }
+    }
+
+    fn baseline(result: &str, size: usize, median: u128) -> Baseline {
+        let res = BaselineResult {
+            result: result.to_string(),
+            serialized_size: size,
+            serialize_time: timing(median),
+            deserialize_time: timing(median),
+            roundtrip_time: timing(2 * median),
+        };
+        Baseline {
+            metadata: RunMetadata {
+                timestamp: 0,
+                version: String::new(),
+                warmup: 0,
+                iterations: 0,
+                alloc_counter: false,
+                os: String::new(),
+                arch: String::new(),
+            },
+            results: BTreeMap::from([(
+                "Scenario".to_string(),
+                BTreeMap::from([("Json".to_string(), res)]),
+            )]),
+        }
+    }
+
+    fn scenario(format: &str, result: &str, size: usize, median: u128) -> Vec<ScenarioResult> {
+        vec![ScenarioResult {
+            name: "Scenario".to_string(),
+            results: vec![FormatResult {
+                format: format.to_string(),
+                result: result.to_string(),
+                serialized_size: size,
+                serialize_time: timing(median),
+                deserialize_time: timing(median),
+                roundtrip_time: timing(2 * median),
+                ..Default::default()
+            }],
+        }]
+    }
+
+    fn status(table: &Table) -> String {
+        table.get_row(0).unwrap().get_cell(6).unwrap().get_content()
+    }
+
+    #[test]
+    fn flags_regressions_beyond_the_threshold() {
+        let base = baseline("Ok", 100, 1000);
+
+        let (table, regressions) = compare(
+            &base,
+            &scenario("Json", "Ok", 100, 1200),
+            10.0,
+            OutputFormat::Markdown,
+        );
+        assert_eq!(regressions, 1);
+        assert_eq!(status(&table), "REGRESSION");
+
+        let (table, regressions) = compare(
+            &base,
+            &scenario("Json", "Ok", 105, 1050),
+            10.0,
+            OutputFormat::Markdown,
+        );
+        assert_eq!(regressions, 0);
+        assert_eq!(status(&table), "ok");
+        assert_eq!(
+            table.get_row(0).unwrap().get_cell(2).unwrap().get_content(),
+            "100 -> 105 (+5.0%)"
+        );
+
+        // Only the size grew.
+        let (_, regressions) = compare(
+            &base,
+            &scenario("Json", "Ok", 120, 1000),
+            10.0,
+            OutputFormat::Markdown,
+        );
+        assert_eq!(regressions, 1);
+    }
+
+    #[test]
+    fn slower_times_within_the_noise_are_no_regression() {
+        let mut base = baseline("Ok", 100, 1000);
+        let res = base
+            .results
+            .get_mut("Scenario")
+            .unwrap()
+            .get_mut("Json")
+            .unwrap();
+        for time in [
+            &mut res.serialize_time,
+            &mut res.deserialize_time,
+            &mut res.roundtrip_time,
+        ] {
+            time.ci_high = 4000;
+        }
+
+        let (table, regressions) = compare(
+            &base,
+            &scenario("Json", "Ok", 100, 1500),
+            10.0,
+            OutputFormat::Markdown,
+        );
+        assert_eq!(regressions, 0);
+        assert_eq!(status(&table), "ok");
+    }
+
+    #[test]
+    fn formats_that_stopped_working_are_regressions() {
+        let base = baseline("Ok", 100, 1000);
+        let (table, regressions) = compare(
+            &base,
+            &scenario("Json", "Deser Err: eof\nat line 1", 100, 0),
+            10.0,
+            OutputFormat::Markdown,
+        );
+        assert_eq!(regressions, 1);
+        assert_eq!(status(&table), "REGRESSION: Deser Err: eof");
+
+        let (table, regressions) = compare(
+            &baseline("Ser Err: oops", 0, 0),
+            &scenario("Json", "Ok", 100, 1000),
+            10.0,
+            OutputFormat::Markdown,
+        );
+        assert_eq!(regressions, 0);
+        assert_eq!(status(&table), "baseline: Ser Err: oops");
+
+        let (table, regressions) = compare(
+            &base,
+            &scenario("Ron", "Ok", 100, 1000),
+            10.0,
+            OutputFormat::Markdown,
+        );
+        assert_eq!(regressions, 0);
+        assert_eq!(status(&table), "not in baseline");
+    }
+
+    #[test]
+    fn names_stay_in_the_baseline_directory() {
+        assert_eq!(parse_name("main").unwrap(), "main");
+        assert_eq!(parse_name("v1.2").unwrap(), "v1.2");
+        assert!(parse_name("").is_err());
+        assert!(parse_name("../main").is_err());
+        assert!(parse_name("a/b").is_err());
+        assert!(parse_name("a\\b").is_err());
+        assert!(parse_name("..").is_err());
+    }
+}
@@ -0,0 +1,461 @@
+//! BSON only takes documents at the top level, other roots are wrapped in a `root` document.
+//! Whether to wrap is read off the root's serde shape, by probes that stop at the first call on
+//! the root, so deciding costs next to nothing and nothing is encoded twice.
+
+use std::fmt;
+
+use serde::{
+    de::{self, DeserializeOwned, Visitor},
+    ser::{self, Impossible},
+    Deserializer, Serialize, Serializer,
+};
+
+/// Key of the document non-document roots are wrapped in.
+pub const KEY: &str = "root";
+
+/// How a type is read at the top level.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Root {
+    /// Never a document, e.g. a sequence or a number, always read from the wrapper.
+    Wrapped,
+    /// A struct or map, or a type only the data tells, e.g. an `Option` or a type that needs
+    /// `deserialize_any`. Read from the wrapper if [`is_wrapper`].
+    Document,
+}
+
+/// Whether `t` needs the wrapper: it isn't a struct or map, or it is one whose only key is
+/// [`KEY`] and would be read as a wrapper.
+pub fn needs_wrapper<T: Serialize + ?Sized>(t: &T) -> bool {
+    match t.serialize(ShapeSerializer) {
+        Err(Shape::Wrap) => true,
+        Err(Shape::Document) => false,
+        Err(Shape::Entries { count, root_key }) => count == 1 && root_key,
+        // Fails on its own, the encoder reports why.
+        Err(Shape::Failed) | Ok(()) => false,
+    }
+}
+
+/// How `T` is read at the top level, from what it asks of the deserializer.
+pub fn root_of<T: DeserializeOwned>() -> Root {
+    match T::deserialize(ShapeDeserializer) {
+        Err(root) => root,
+        Ok(_) => Root::Document,
+    }
+}
+
+/// Whether a document was written by [`needs_wrapper`], a lone [`KEY`] entry. Unambiguous, as
+/// documents of that shape are wrapped too.
+pub fn is_wrapper(doc: &bson::RawDocument) -> bson::raw::Result<bool> {
+    let mut keys = doc.into_iter().map(|el| el.map(|(key, _)| key));
+    Ok(keys.next().transpose()? == Some(KEY) && keys.next().is_none())
+}
+
+/// What [`ShapeSerializer`] found, carried as its error so it stops right away.
+#[derive(Debug)]
+enum Shape {
+    Wrap,
+    Document,
+    /// A struct or map that might have a lone [`KEY`] entry, counted up to two.
+    Entries {
+        count: usize,
+        root_key: bool,
+    },
+    Failed,
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for Shape {}
+
+impl ser::Error for Shape {
+    fn custom<M: fmt::Display>(_msg: M) -> Self {
+        Shape::Failed
+    }
+}
+
+macro_rules! wrap {
+    ($($method:ident($($arg:ty),*)),+ $(,)?) => {
+        $(fn $method(self, $(_: $arg),*) -> Result<Self::Ok, Shape> {
+            Err(Shape::Wrap)
+        })+
+    };
+}
+
+struct ShapeSerializer;
+
+impl Serializer for ShapeSerializer {
+    type Ok = ();
+    type Error = Shape;
+    type SerializeSeq = Impossible<(), Shape>;
+    type SerializeTuple = Impossible<(), Shape>;
+    type SerializeTupleStruct = Impossible<(), Shape>;
+    type SerializeTupleVariant = Impossible<(), Shape>;
+    type SerializeMap = Entries;
+    type SerializeStruct = Entries;
+    type SerializeStructVariant = Impossible<(), Shape>;
+
+    wrap!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_none(),
+        serialize_unit(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    );
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Shape> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Shape> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Shape> {
+        Err(Shape::Wrap)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Shape> {
+        Err(Shape::Wrap)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Shape> {
+        Err(Shape::Wrap)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Shape> {
+        Err(Shape::Wrap)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Shape> {
+        Err(Shape::Wrap)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Shape> {
+        match len {
+            Some(1) | None => Ok(Entries::default()),
+            Some(_) => Err(Shape::Document),
+        }
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Entries, Shape> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Shape> {
+        Err(Shape::Wrap)
+    }
+}
+
+/// Counts the entries of a struct or map and checks the first key, values are skipped.
+#[derive(Default)]
+struct Entries {
+    count: usize,
+    root_key: bool,
+}
+
+impl Entries {
+    fn key(&mut self, is_root_key: impl FnOnce() -> bool) -> Result<(), Shape> {
+        if self.count == 0 {
+            self.root_key = is_root_key();
+        }
+        self.count += 1;
+        match self.count {
+            1 => Ok(()),
+            _ => Err(Shape::Document),
+        }
+    }
+
+    fn end(self) -> Result<(), Shape> {
+        Err(Shape::Entries {
+            count: self.count,
+            root_key: self.root_key,
+        })
+    }
+}
+
+impl ser::SerializeMap for Entries {
+    type Ok = ();
+    type Error = Shape;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Shape> {
+        self.key(|| serde_json::to_value(key).is_ok_and(|key| key == KEY))
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, _value: &T) -> Result<(), Shape> {
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), Shape> {
+        Entries::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Entries {
+    type Ok = ();
+    type Error = Shape;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        _value: &T,
+    ) -> Result<(), Shape> {
+        self.key(|| key == KEY)
+    }
+
+    fn end(self) -> Result<(), Shape> {
+        Entries::end(self)
+    }
+}
+
+impl fmt::Display for Root {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for Root {}
+
+impl de::Error for Root {
+    fn custom<M: fmt::Display>(_msg: M) -> Self {
+        Root::Document
+    }
+}
+
+macro_rules! wrapped {
+    ($($method:ident($($arg:ty),*)),+ $(,)?) => {
+        $(fn $method<V: Visitor<'de>>(self, $(_: $arg,)* _visitor: V) -> Result<V::Value, Root> {
+            Err(Root::Wrapped)
+        })+
+    };
+}
+
+/// Fails with the [`Root`] of the type on the first call.
+struct ShapeDeserializer;
+
+impl<'de> Deserializer<'de> for ShapeDeserializer {
+    type Error = Root;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Root> {
+        Err(Root::Document)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Root> {
+        Err(Root::Document)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Root> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Root> {
+        Err(Root::Document)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Root> {
+        Err(Root::Document)
+    }
+
+    wrapped!(
+        deserialize_bool(),
+        deserialize_i8(),
+        deserialize_i16(),
+        deserialize_i32(),
+        deserialize_i64(),
+        deserialize_i128(),
+        deserialize_u8(),
+        deserialize_u16(),
+        deserialize_u32(),
+        deserialize_u64(),
+        deserialize_u128(),
+        deserialize_f32(),
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_str(),
+        deserialize_string(),
+        deserialize_bytes(),
+        deserialize_byte_buf(),
+        deserialize_unit(),
+        deserialize_unit_struct(&'static str),
+        deserialize_seq(),
+        deserialize_tuple(usize),
+        deserialize_tuple_struct(&'static str, usize),
+        deserialize_enum(&'static str, &'static [&'static str]),
+        deserialize_identifier(),
+        deserialize_ignored_any(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::*;
+    use crate::formats::{Bson, Deserializes, Serializes};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    /// A document that looks like the wrapper.
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct LoneRoot {
+        root: i32,
+    }
+
+    fn roundtrip<T: Serialize + DeserializeOwned>(value: &T) -> (bson::Document, T) {
+        let (_, bytes) = <Bson as Serializes<T>>::serialize(value).unwrap();
+        let doc = bson::from_slice(&bytes).unwrap();
+        (doc, <Bson as Deserializes<T>>::deserialize(bytes).unwrap())
+    }
+
+    #[test]
+    fn wraps_only_non_documents() {
+        assert!(needs_wrapper(&vec![1, 2]));
+        assert!(needs_wrapper(&5u8));
+        assert!(needs_wrapper(&"text"));
+        assert!(needs_wrapper(&None::<Point>));
+        assert!(needs_wrapper(&Some(5)));
+        assert!(needs_wrapper(&LoneRoot { root: 1 }));
+        assert!(needs_wrapper(&json!([1, 2])));
+        assert!(needs_wrapper(&json!({ "root": 1 })));
+        assert!(!needs_wrapper(&Point { x: 1, y: 2 }));
+        assert!(!needs_wrapper(&Some(Point { x: 1, y: 2 })));
+        assert!(!needs_wrapper(&BTreeMap::from([("a", 1)])));
+        assert!(!needs_wrapper(&json!({ "a": 1 })));
+    }
+
+    #[test]
+    fn reads_roots_by_type() {
+        assert_eq!(root_of::<Vec<u32>>(), Root::Wrapped);
+        assert_eq!(root_of::<f64>(), Root::Wrapped);
+        assert_eq!(root_of::<String>(), Root::Wrapped);
+        assert_eq!(root_of::<Point>(), Root::Document);
+        assert_eq!(root_of::<LoneRoot>(), Root::Document);
+        assert_eq!(root_of::<Option<u32>>(), Root::Document);
+        assert_eq!(root_of::<BTreeMap<String, u32>>(), Root::Document);
+        assert_eq!(root_of::<serde_json::Value>(), Root::Document);
+    }
+
+    #[test]
+    fn recognizes_the_wrapper() {
+        let is_wrapper = |doc: bson::Document| {
+            let bytes = bson::to_vec(&doc).unwrap();
+            is_wrapper(bson::RawDocument::from_bytes(&bytes).unwrap()).unwrap()
+        };
+        assert!(is_wrapper(bson::doc! { "root": 1 }));
+        assert!(!is_wrapper(bson::doc! { "root": 1, "other": 2 }));
+        assert!(!is_wrapper(bson::doc! { "other": 1 }));
+        assert!(!is_wrapper(bson::doc! {}));
+    }
+
+    #[test]
+    fn roundtrips_a_seq() {
+        let (doc, back) = roundtrip(&vec![1u32, 2, 3]);
+        // BSON has no unsigned integers, u32 is written as i64.
+        assert_eq!(doc, bson::doc! { "root": [1i64, 2i64, 3i64] });
+        assert_eq!(back, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn roundtrips_a_primitive() {
+        let (doc, back) = roundtrip(&1.5f64);
+        assert_eq!(doc, bson::doc! { "root": 1.5 });
+        assert_eq!(back, 1.5);
+    }
+
+    #[test]
+    fn roundtrips_options() {
+        assert_eq!(roundtrip(&Some(7i32)).1, Some(7));
+        assert_eq!(roundtrip(&None::<i32>).1, None);
+        let point = Some(Point { x: 1, y: 2 });
+        let (doc, back) = roundtrip(&point);
+        assert_eq!(doc, bson::doc! { "x": 1, "y": 2 });
+        assert_eq!(back, point);
+    }
+
+    #[test]
+    fn roundtrips_a_struct_with_a_lone_root_field() {
+        let (doc, back) = roundtrip(&LoneRoot { root: 3 });
+        assert_eq!(doc, bson::doc! { "root": { "root": 3 } });
+        assert_eq!(back, LoneRoot { root: 3 });
+    }
+
+    #[test]
+    fn roundtrips_documents_unwrapped() {
+        let (doc, back) = roundtrip(&Point { x: 1, y: 2 });
+        assert_eq!(doc, bson::doc! { "x": 1, "y": 2 });
+        assert_eq!(back, Point { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn roundtrips_json_values() {
+        let array = json!([1, "two", { "three": 3 }]);
+        let (doc, back) = roundtrip(&array);
+        assert_eq!(doc, bson::doc! { "root": [1i64, "two", { "three": 3i64 }] });
+        assert_eq!(back, array);
+
+        let lone_root = json!({ "root": [1] });
+        assert_eq!(roundtrip(&lone_root).1, lone_root);
+        let object = json!({ "a": 1 });
+        assert_eq!(roundtrip(&object).1, object);
+    }
+}
@@ -0,0 +1,310 @@
+use std::path::PathBuf;
+
+use clap::{builder::RangedU64ValueParser, ArgGroup, Parser, ValueEnum};
+
+use crate::{baseline, BenchConfig};
+
+/// Compares serde formats by roundtripping aggregation results through them.
+#[derive(Debug, Parser)]
+#[command(about)]
+// Options that need --dos require this group instead of --dos itself: clap drops requirements
+// that conflict with a given argument, and --dos conflicts with e.g. --scenario.
+#[command(group(ArgGroup::new("dos_given").arg("dos")))]
+pub struct Cli {
+    /// List the available scenarios and formats and exit.
+    #[arg(long)]
+    pub list: bool,
+    /// Run all scenarios and formats and rewrite the results sections of the README.
+    #[arg(long, conflicts_with_all = ["scenarios", "formats", "output", "inputs", "sweep"])]
+    pub update_readme: bool,
+    /// Run your own JSON or JSONL file as `serde_json::Value` instead of the built-in scenarios.
+    /// Directories are searched for .json and .jsonl files. Can be repeated.
+    #[arg(long = "input", value_name = "PATH")]
+    pub inputs: Vec<PathBuf>,
+    /// Also run each input with a schema inferred from it, for formats that need one.
+    #[arg(long, requires = "inputs")]
+    pub infer_schema: bool,
+    /// Run the generated scenarios from 10 buckets up to --sweep-max buckets, and report how
+    /// size and time grow. Larger sizes are measured with fewer iterations.
+    #[arg(long, conflicts_with = "inputs")]
+    pub sweep: bool,
+    /// Largest number of buckets of the sweep.
+    #[arg(
+        long,
+        value_name = "BUCKETS",
+        default_value_t = 1_000_000,
+        requires = "sweep",
+        value_parser = RangedU64ValueParser::<usize>::new().range(10..)
+    )]
+    pub sweep_max: usize,
+    /// Levels of sub-aggregations in "Nested Aggregations".
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    pub depth: u64,
+    /// Buckets per sub-aggregation in "Nested Aggregations". The tree has FAN_OUT^DEPTH leaves.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub fan_out: u64,
+    /// Find the nesting depth at which each format fails, with one child process per attempt so
+    /// stack overflows can be reported.
+    #[arg(long, conflicts_with_all = ["inputs", "sweep", "scenarios", "update_readme"])]
+    pub probe_nesting: bool,
+    /// Deepest nesting tried by --probe-nesting.
+    #[arg(
+        long,
+        value_name = "DEPTH",
+        default_value_t = 10_000,
+        requires = "probe_nesting",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub max_depth: u64,
+    /// Roundtrip random aggregation results through every format instead of running the
+    /// scenarios, and print a minimal reproducer for each format that fails.
+    #[arg(
+        long,
+        conflicts_with_all = ["inputs", "sweep", "scenarios", "update_readme", "probe_nesting"]
+    )]
+    pub fuzz: bool,
+    /// Random values per type and format tried by --fuzz.
+    #[arg(long, value_name = "N", default_value_t = 256, requires = "fuzz")]
+    pub fuzz_cases: u32,
+    /// Seed of --fuzz, runs with the same seed try the same values.
+    #[arg(
+        long,
+        value_name = "SEED",
+        default_value_t = 0x5eed_da7a,
+        requires = "fuzz"
+    )]
+    pub fuzz_seed: u64,
+    /// Don't generate NaN in --fuzz, to find failures other than the formats that lose NaN.
+    #[arg(long, requires = "fuzz")]
+    pub no_nan: bool,
+    /// Print which format supports which serde feature instead of running the scenarios.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "inputs",
+            "sweep",
+            "scenarios",
+            "update_readme",
+            "probe_nesting",
+            "fuzz",
+        ]
+    )]
+    pub compat: bool,
+    /// Feed every format corrupted versions of its own output instead of measuring it: truncated,
+    /// with single bits flipped and with random bytes overwritten. Reports which decoders return an
+    /// error, a wrong value, panic, abort, run out of memory or hang. Decoding runs in child
+    /// processes.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "sweep",
+            "update_readme",
+            "probe_nesting",
+            "fuzz",
+            "compat",
+            "save_baseline",
+            "baseline",
+        ]
+    )]
+    pub robustness: bool,
+    /// Corrupted inputs of each kind per scenario and format tried by --robustness.
+    #[arg(long, value_name = "N", default_value_t = 100, requires = "robustness")]
+    pub corruptions: usize,
+    /// Decodes the corrupted inputs of one format in --robustness, used with
+    /// --robustness-scenario.
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        hide = true,
+        requires_all = ["robustness", "robustness_scenario"]
+    )]
+    pub robustness_child: Option<String>,
+    /// The scenario the --robustness child runs.
+    #[arg(long, value_name = "NAME", hide = true)]
+    pub robustness_scenario: Option<String>,
+    /// The first corrupted input the --robustness child decodes, earlier children did the ones
+    /// before.
+    #[arg(long, value_name = "N", hide = true, default_value_t = 0)]
+    pub robustness_from: usize,
+    /// Decode hostile payloads that claim billions of elements or a 64 MiB string, and report
+    /// which formats allocate for the claim. Each payload is decoded in a child process.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "inputs",
+            "sweep",
+            "scenarios",
+            "update_readme",
+            "probe_nesting",
+            "fuzz",
+            "compat",
+            "robustness",
+        ]
+    )]
+    pub dos: bool,
+    /// Memory a decoder may allocate in --dos before the allocation fails. Only enforced with the
+    /// `alloc-counter` feature.
+    #[arg(
+        long,
+        value_name = "MIB",
+        default_value_t = 1024,
+        requires = "dos_given",
+        // At most as many MiB as fit into `usize` bytes.
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..=(usize::MAX >> 20) as u64)
+    )]
+    pub memory_limit: usize,
+    /// Runs one format in --dos, used with --dos-attack.
+    #[arg(long, value_name = "FORMAT", hide = true, requires = "dos_attack")]
+    pub dos_child: Option<String>,
+    /// The attack the --dos child runs.
+    #[arg(long, value_name = "NAME", hide = true)]
+    pub dos_attack: Option<String>,
+    /// Runs one format at --depth, used by --probe-nesting.
+    #[arg(long, value_name = "FORMAT", hide = true)]
+    pub nesting_child: Option<String>,
+    /// Only run scenarios whose name contains NAME, case-insensitive. Can be repeated.
+    #[arg(
+        short,
+        long = "scenario",
+        value_name = "NAME",
+        conflicts_with = "inputs"
+    )]
+    pub scenarios: Vec<String>,
+    /// Only run formats whose name contains NAME, case-insensitive. Can be repeated.
+    #[arg(short, long = "format", value_name = "NAME")]
+    pub formats: Vec<String>,
+    /// Timed iterations per format.
+    #[arg(short, long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub iterations: u64,
+    /// Untimed warmup iterations per format.
+    #[arg(short, long, default_value_t = 10)]
+    pub warmup: u64,
+    /// How deserialized floats are compared with the original ones. --fuzz and the Float Edge
+    /// Cases scenario always compare bitwise.
+    #[arg(long, value_enum, default_value_t = FloatCmp::PartialEq, conflicts_with = "fuzz")]
+    pub float_cmp: FloatCmp,
+    /// How results are printed.
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Markdown)]
+    pub output: OutputFormat,
+    /// Write JSON or CSV results to this file instead of stdout.
+    #[arg(long, value_name = "PATH")]
+    pub output_file: Option<PathBuf>,
+    /// Save the results as baseline NAME, in `baselines/NAME.json`.
+    #[arg(long, value_name = "NAME", value_parser = baseline::parse_name)]
+    pub save_baseline: Option<String>,
+    /// Compare the results with baseline NAME and exit with an error on regressions.
+    #[arg(long, value_name = "NAME", value_parser = baseline::parse_name)]
+    pub baseline: Option<String>,
+    /// Growth in percent of a size or median time that counts as a regression.
+    #[arg(long, value_name = "PERCENT", default_value_t = 10.0)]
+    pub regression_threshold: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Markdown table, as used in the README.
+    Markdown,
+    /// Plain text table with ASCII borders.
+    Plain,
+    /// All results including the raw samples and run metadata as JSON.
+    Json,
+    /// One line per scenario and format, including run metadata.
+    Csv,
+}
+
+impl OutputFormat {
+    /// Tables are printed per scenario on stdout, the other outputs are written at the end.
+    pub fn is_table(self) -> bool {
+        matches!(self, OutputFormat::Markdown | OutputFormat::Plain)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FloatCmp {
+    /// With `==` of the types: NaN is never equal, even to itself, and -0.0 equals 0.0.
+    PartialEq,
+    /// Floats must have the same bits, the sign of zero and NaN payloads included. Compares the
+    /// serialized form of the values, `PartialEq` impls are not used.
+    Bitwise,
+}
+
+impl FloatCmp {
+    pub fn floats_eq(self, l: f64, r: f64) -> bool {
+        match self {
+            FloatCmp::PartialEq => l == r,
+            FloatCmp::Bitwise => l.to_bits() == r.to_bits(),
+        }
+    }
+}
+
+impl Cli {
+    pub fn bench_config(&self) -> BenchConfig {
+        BenchConfig {
+            warmup: self.warmup as usize,
+            iterations: self.iterations as usize,
+            float_cmp: self.float_cmp,
+            corruptions: self.corruptions,
+        }
+    }
+
+    pub fn runs_scenario(&self, name: &str) -> bool {
+        // A --robustness child only runs the scenario it was started for.
+        if let Some(scenario) = &self.robustness_scenario {
+            return scenario == name;
+        }
+        matches_filter(&self.scenarios, name)
+    }
+
+    pub fn runs_format(&self, name: &str) -> bool {
+        matches_filter(&self.formats, name)
+    }
+}
+
+/// An empty filter matches everything.
+fn matches_filter(filters: &[String], name: &str) -> bool {
+    let name = name.to_lowercase();
+    filters.is_empty()
+        || filters
+            .iter()
+            .any(|filter| name.contains(&filter.to_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("test_serde_formats").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn arguments_are_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn filters_match_parts_of_names_ignoring_case() {
+        let filters = ["json".to_string(), "Pack".to_string()];
+        assert!(matches_filter(&filters, "Json"));
+        assert!(matches_filter(&filters, "MessagePack"));
+        assert!(!matches_filter(&filters, "Bincode"));
+        assert!(matches_filter(&[], "Bincode"));
+    }
+
+    #[test]
+    fn scenarios_cant_filter_inputs() {
+        assert!(parse(&["--scenario", "flat"]).is_ok());
+        assert!(parse(&["--input", "data.json"]).is_ok());
+        assert!(parse(&["--input", "data.json", "--scenario", "flat"]).is_err());
+    }
+
+    #[test]
+    fn sweeps_start_at_ten_buckets() {
+        let cli = parse(&["--sweep", "--sweep-max", "10"]).unwrap();
+        assert_eq!(cli.sweep_max, 10);
+        assert!(parse(&["--sweep", "--sweep-max", "9"]).is_err());
+        assert!(parse(&["--sweep", "--sweep-max", "0"]).is_err());
+    }
+}
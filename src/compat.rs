@@ -0,0 +1,341 @@
+//! Roundtrips one small type per serde feature through every format and prints a grid of which
+//! format supports which feature.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use prettytable::{cell, Row, Table};
+
+use crate::{
+    cli::{Cli, FloatCmp, OutputFormat},
+    cmp_res,
+    diff::Diff,
+    error_result, first_line,
+    formats::{self, Deserializes, FormatList, FormatVisitor, Formats, Serializes},
+    get_table,
+    panic_guard::catch_panic,
+    test_struct_compat::*,
+};
+
+/// One row of the grid, the result of each format.
+struct Feature {
+    name: &'static str,
+    run: fn() -> Vec<(String, String)>,
+}
+
+fn inner() -> Inner {
+    Inner {
+        a: 1,
+        b: "inner".to_string(),
+    }
+}
+
+fn features() -> Vec<Feature> {
+    vec![
+        Feature {
+            name: "internally tagged enum",
+            run: || {
+                roundtrip(&vec![
+                    InternallyTagged::Unit,
+                    InternallyTagged::Newtype(inner()),
+                    InternallyTagged::Struct {
+                        a: 2,
+                        b: "struct".to_string(),
+                    },
+                ])
+            },
+        },
+        Feature {
+            name: "adjacently tagged enum",
+            run: || {
+                roundtrip(&vec![
+                    AdjacentlyTagged::Unit,
+                    AdjacentlyTagged::Newtype(1),
+                    AdjacentlyTagged::Tuple(2, "tuple".to_string()),
+                    AdjacentlyTagged::Struct { a: 3 },
+                ])
+            },
+        },
+        Feature {
+            name: "untagged enum",
+            run: || {
+                roundtrip(&vec![
+                    Untagged::Int(1),
+                    Untagged::Text("text".to_string()),
+                    Untagged::Struct { a: 2, b: true },
+                ])
+            },
+        },
+        Feature {
+            name: "flatten",
+            run: || {
+                roundtrip(&Flattened {
+                    id: 7,
+                    inner: inner(),
+                })
+            },
+        },
+        Feature {
+            name: "skip_serializing_if",
+            run: || {
+                roundtrip(&vec![
+                    SkipIf {
+                        a: 1,
+                        b: None,
+                        c: 2,
+                    },
+                    SkipIf {
+                        a: 3,
+                        b: Some(4),
+                        c: 5,
+                    },
+                ])
+            },
+        },
+        Feature {
+            name: "default (field added)",
+            run: || {
+                check(
+                    &Before { a: 1 },
+                    &After {
+                        a: 1,
+                        b: default_b(),
+                    },
+                )
+            },
+        },
+        Feature {
+            name: "u128 / i128",
+            run: || {
+                roundtrip(&Wide {
+                    unsigned: u128::MAX,
+                    signed: i128::MIN,
+                })
+            },
+        },
+        Feature {
+            name: "char",
+            run: || {
+                roundtrip(&Chars {
+                    chars: vec!['a', '\0', 'ß', '€', '🦀'],
+                })
+            },
+        },
+        Feature {
+            name: "Option<Option<T>>",
+            run: || {
+                roundtrip(&NestedOption {
+                    values: vec![None, Some(None), Some(Some(1))],
+                })
+            },
+        },
+        Feature {
+            name: "unit struct",
+            run: || roundtrip(&UnitStruct),
+        },
+        Feature {
+            name: "newtype struct",
+            run: || roundtrip(&NewtypeStruct(7)),
+        },
+        Feature {
+            name: "tuple struct",
+            run: || roundtrip(&TupleStruct(7, "tuple".to_string(), true)),
+        },
+        Feature {
+            name: "integer map keys",
+            run: || {
+                roundtrip(&IntKeys {
+                    map: BTreeMap::from([(i64::MIN, 1), (-1, 2), (0, 3), (i64::MAX, 4)]),
+                })
+            },
+        },
+        Feature {
+            name: "tuple map keys",
+            run: || {
+                roundtrip(&TupleKeys {
+                    map: BTreeMap::from([((0, 1), 1), ((1, 0), 2)]),
+                })
+            },
+        },
+        Feature {
+            name: "serialize_bytes",
+            run: || {
+                roundtrip(&Bytes {
+                    data: (0..=255).collect(),
+                })
+            },
+        },
+    ]
+}
+
+fn roundtrip<T: Formats + PartialEq + Diff>(value: &T) -> Vec<(String, String)> {
+    results(formats::list(&Check {
+        written: value,
+        expected: value,
+    }))
+}
+
+/// Writes `written` with every format, and expects to read `expected` back.
+fn check<W: Formats<R>, R: PartialEq + Diff>(written: &W, expected: &R) -> Vec<(String, String)> {
+    results(formats::list_pair(&Check { written, expected }))
+}
+
+/// Formats that can't handle the type are "n/a".
+fn results(formats: FormatList<String>) -> Vec<(String, String)> {
+    formats
+        .into_iter()
+        .map(|(format, result)| (format, result.unwrap_or_else(|| "n/a".to_string())))
+        .collect()
+}
+
+/// Checks a format, see [`check`].
+struct Check<'a, W, R> {
+    written: &'a W,
+    expected: &'a R,
+}
+
+impl<W, R: PartialEq + Diff> FormatVisitor<W, R> for Check<'_, W, R> {
+    type Output = String;
+    fn visit<F: Serializes<W> + Deserializes<R>>(&self) -> String {
+        check_format::<W, R, F>(self.written, self.expected)
+    }
+}
+
+fn check_format<W, R: PartialEq + Diff, F: Serializes<W> + Deserializes<R>>(
+    written: &W,
+    expected: &R,
+) -> String {
+    match catch_panic(|| F::serialize(written)) {
+        Ok(Ok((_, serialized))) => match catch_panic(|| F::deserialize(serialized)) {
+            // None of the features involves floats.
+            Ok(Ok(deser)) => match cmp_res(expected, &deser, FloatCmp::PartialEq) {
+                Ok(()) => "Ok".to_string(),
+                Err(mismatches) => format!("Cmp Mismatch: {}", mismatches.join("; ")),
+            },
+            Ok(Err(err)) => error_result::<F>("Deser Err", &err),
+            Err(panic) => format!("Deser Panic: {panic}"),
+        },
+        Ok(Err(err)) => error_result::<F>("Ser Err", &err),
+        Err(panic) => format!("Ser Panic: {panic}"),
+    }
+}
+
+/// The results of all features, in the order of [`features`].
+struct Matrix {
+    formats: Vec<String>,
+    rows: Vec<(&'static str, Vec<(String, String)>)>,
+}
+
+impl Matrix {
+    fn run() -> Matrix {
+        let rows: Vec<_> = features()
+            .into_iter()
+            .map(|feature| (feature.name, (feature.run)()))
+            .collect();
+        let formats = rows[0].1.iter().map(|(format, _)| format.clone()).collect();
+        Matrix { formats, rows }
+    }
+
+    fn retain_formats(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.formats.retain(|format| keep(format));
+        for (_, results) in &mut self.rows {
+            results.retain(|(format, _)| keep(format));
+        }
+    }
+
+    /// One row per feature, one column per format, with the kind of result only. The errors are
+    /// in [`Matrix::notes`].
+    fn table(&self, output: OutputFormat) -> Table {
+        let mut table = get_table(output);
+        let mut row = Row::empty();
+        row.add_cell(cell!["Feature"]);
+        for format in &self.formats {
+            row.add_cell(cell!(format));
+        }
+        table.set_titles(row);
+        for (feature, results) in &self.rows {
+            let mut row = Row::empty();
+            row.add_cell(cell!(feature));
+            for (_, result) in results {
+                row.add_cell(cell!(short_result(result)));
+            }
+            table.add_row(row);
+        }
+        table
+    }
+
+    /// The error of every failed feature and format.
+    fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        for (feature, results) in &self.rows {
+            for (format, result) in results {
+                if !matches!(short_result(result), "Ok" | "n/a") {
+                    let error = first_line(result);
+                    notes.push(format!("{feature}, {format}: {error}"));
+                }
+            }
+        }
+        notes
+    }
+}
+
+/// The kind of result, the part before the error message.
+fn short_result(result: &str) -> &str {
+    result.split(':').next().unwrap_or_default()
+}
+
+/// Runs the matrix and prints it for the formats selected with `--format`.
+pub fn run(cli: &Cli) -> Result<()> {
+    let mut matrix = Matrix::run();
+    matrix.retain_formats(|format| cli.runs_format(format));
+    if matrix.formats.is_empty() {
+        bail!("no format matches {:?}, see --list", cli.formats);
+    }
+    println!("Serde features:");
+    matrix.table(cli.output).printstd();
+    for note in matrix.notes() {
+        println!("- {note}");
+    }
+    Ok(())
+}
+
+/// The matrix of all formats with the notes below it, for the README.
+pub fn readme_section() -> String {
+    let matrix = Matrix::run();
+    let mut section = matrix.table(OutputFormat::Markdown).to_string();
+    section.push('\n');
+    for note in matrix.notes() {
+        section.push_str(&format!("- {note}\n"));
+    }
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results_of(name: &str) -> BTreeMap<String, String> {
+        let feature = features()
+            .into_iter()
+            .find(|feature| feature.name == name)
+            .unwrap();
+        (feature.run)().into_iter().collect()
+    }
+
+    #[test]
+    fn missing_128_bit_integers_are_unsupported() {
+        let results = results_of("u128 / i128");
+        assert_eq!(results["RON"], "Unsupported: u128 is not supported");
+        assert_eq!(results["BSON"], "Unsupported: u128 is not supported");
+        assert_eq!(results["Json"], "Ok");
+        assert_eq!(results["Minicbor"], "n/a");
+    }
+
+    #[test]
+    fn deserialize_any_is_unsupported() {
+        let results = results_of("untagged enum");
+        assert_eq!(short_result(&results["Bincode"]), "Unsupported");
+        assert_eq!(short_result(&results["Bitcode"]), "Unsupported");
+        assert_eq!(short_result(&results["Postcard"]), "Unsupported");
+    }
+}
@@ -0,0 +1,649 @@
+//! Explains a comparison mismatch as a list of paths whose values differ, like
+//! `aggs_res.values[0].Bucket.Terms.entries["abc"].doc_count: 5 != 6`.
+//!
+//! Both values are serialized into a [`Node`] tree first, which keeps what the serde data model
+//! has, unlike `serde_json::Value`: variant names, non-string map keys, NaN and infinities, and
+//! the difference between integers and floats. Maps are compared by key, so the order of a
+//! `HashMap` doesn't matter.
+//!
+//! With [`FloatCmp::Bitwise`] the trees are also what decides whether the values are equal.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    mem,
+};
+
+use serde::{ser, Serialize};
+
+use crate::cli::FloatCmp;
+
+/// Differences listed per value, the rest is only counted.
+const MAX_DIFFS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Unit,
+    Bool(bool),
+    Int(i128),
+    UInt(u128),
+    Float(f64),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    None,
+    Some(Box<Node>),
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Struct(Vec<(&'static str, Node)>),
+    Variant(&'static str, Box<Node>),
+}
+
+impl Node {
+    /// A short description, the contents of collections and structs are left out.
+    fn describe(&self) -> String {
+        match self {
+            Node::Unit => "()".to_string(),
+            Node::Bool(val) => val.to_string(),
+            Node::Int(val) => val.to_string(),
+            Node::UInt(val) => val.to_string(),
+            Node::Float(val) => format!("{val:?}"),
+            Node::Char(val) => format!("{val:?}"),
+            Node::Str(val) if val.chars().count() > 40 => {
+                format!("{:?}..", val.chars().take(40).collect::<String>())
+            }
+            Node::Str(val) => format!("{val:?}"),
+            Node::Bytes(bytes) => format!("{} bytes", bytes.len()),
+            Node::None => "None".to_string(),
+            Node::Some(inner) => format!("Some({})", inner.describe()),
+            Node::Seq(items) => format!("[{} items]", items.len()),
+            Node::Map(entries) => format!("{{{} entries}}", entries.len()),
+            Node::Struct(_) => "{..}".to_string(),
+            Node::Variant(name, inner) if **inner == Node::Unit => name.to_string(),
+            Node::Variant(name, _) => format!("{name}(..)"),
+        }
+    }
+}
+
+/// A type whose deserialized values are explained when they differ from the original. Serde
+/// types are compared through their [`Node`] trees, other types implement it themselves.
+pub trait Diff {
+    /// The paths at which `self` and `other` differ, with floats compared by `float_cmp`. Empty
+    /// if they are the same, with [`FloatCmp::Bitwise`] this decides whether they are equal.
+    fn diff(&self, other: &Self, float_cmp: FloatCmp) -> Vec<String>;
+}
+
+impl<T: Serialize> Diff for T {
+    fn diff(&self, other: &Self, float_cmp: FloatCmp) -> Vec<String> {
+        let (orig, deser) = match (
+            self.serialize(NodeSerializer),
+            other.serialize(NodeSerializer),
+        ) {
+            (Ok(orig), Ok(deser)) => (orig, deser),
+            (Err(err), _) | (_, Err(err)) => return vec![format!("could not compare: {err}")],
+        };
+        let mut differ = Differ {
+            float_cmp,
+            lines: Vec::new(),
+            omitted: 0,
+        };
+        differ.node("", &orig, &deser);
+        let mut lines = differ.lines;
+        if differ.omitted > 0 {
+            lines.push(format!("... and {} more", differ.omitted));
+        }
+        lines
+    }
+}
+
+struct Differ {
+    float_cmp: FloatCmp,
+    lines: Vec<String>,
+    omitted: usize,
+}
+
+impl Differ {
+    fn report(&mut self, path: &str, line: impl FnOnce() -> String) {
+        if self.lines.len() < MAX_DIFFS {
+            let path = if path.is_empty() { "<root>" } else { path };
+            self.lines.push(format!("{path}: {}", line()));
+        } else {
+            self.omitted += 1;
+        }
+    }
+
+    fn node(&mut self, path: &str, orig: &Node, deser: &Node) {
+        match (orig, deser) {
+            (Node::Some(orig), Node::Some(deser)) => self.node(path, orig, deser),
+            (Node::Variant(orig_name, orig), Node::Variant(deser_name, deser))
+                if orig_name == deser_name =>
+            {
+                self.node(&join(path, orig_name), orig, deser)
+            }
+            (Node::Struct(orig), Node::Struct(deser)) => self.fields(path, orig, deser),
+            (Node::Seq(orig), Node::Seq(deser)) => {
+                for (i, (orig, deser)) in orig.iter().zip(deser).enumerate() {
+                    self.node(&format!("{path}[{i}]"), orig, deser);
+                }
+                if orig.len() != deser.len() {
+                    self.report(path, || {
+                        format!("{} items != {} items", orig.len(), deser.len())
+                    });
+                }
+            }
+            (Node::Map(orig), Node::Map(deser)) => self.map(path, orig, deser),
+            (Node::Float(orig), Node::Float(deser)) if !self.float_cmp.floats_eq(*orig, *deser) => {
+                self.report(path, || match (format!("{orig:?}"), format!("{deser:?}")) {
+                    // NaN payloads and signs don't show up in the debug output.
+                    (orig_text, deser_text)
+                        if orig_text == deser_text && orig.to_bits() != deser.to_bits() =>
+                    {
+                        format!(
+                            "{orig_text} ({:#018x}) != {deser_text} ({:#018x})",
+                            orig.to_bits(),
+                            deser.to_bits()
+                        )
+                    }
+                    (orig_text, deser_text) => format!("{orig_text} != {deser_text}"),
+                })
+            }
+            (Node::Float(_), Node::Float(_)) => {}
+            (orig, deser) if orig != deser => self.report(path, || {
+                format!("{} != {}", orig.describe(), deser.describe())
+            }),
+            _ => {}
+        }
+    }
+
+    /// Fields are matched by name, `skip_serializing_if` can leave some out.
+    fn fields(&mut self, path: &str, orig: &[(&str, Node)], deser: &[(&str, Node)]) {
+        for (name, orig) in orig {
+            let path = join(path, name);
+            match deser.iter().find(|(deser_name, _)| deser_name == name) {
+                Some((_, deser)) => self.node(&path, orig, deser),
+                None => self.report(&path, || format!("{} != missing", orig.describe())),
+            }
+        }
+        for (name, deser) in deser {
+            if !orig.iter().any(|(orig_name, _)| orig_name == name) {
+                self.report(&join(path, name), || {
+                    format!("missing != {}", deser.describe())
+                });
+            }
+        }
+    }
+
+    /// Entries are matched by their whole key, the described key is only for the path.
+    fn map(&mut self, path: &str, orig: &[(Node, Node)], deser: &[(Node, Node)]) {
+        let mut deser: HashMap<Key, &Node> =
+            deser.iter().map(|(key, value)| (Key(key), value)).collect();
+        for (key, orig) in orig {
+            let path = format!("{path}[{}]", key.describe());
+            match deser.remove(&Key(key)) {
+                Some(deser) => self.node(&path, orig, deser),
+                None => self.report(&path, || format!("{} != missing", orig.describe())),
+            }
+        }
+        let mut added: Vec<_> = deser
+            .into_iter()
+            .map(|(key, deser)| (key.0.describe(), deser))
+            .collect();
+        added.sort_by(|(l, _), (r, _)| l.cmp(r));
+        for (key, deser) in added {
+            self.report(&format!("{path}[{key}]"), || {
+                format!("missing != {}", deser.describe())
+            });
+        }
+    }
+}
+
+/// A map key, equal to another if it has the same tree. Floats are the same if their bits are,
+/// so NaN keys find themselves.
+struct Key<'a>(&'a Node);
+
+impl PartialEq for Key<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        let pairs = |l: &[(Node, Node)], r: &[(Node, Node)]| {
+            l.len() == r.len()
+                && l.iter()
+                    .zip(r)
+                    .all(|(l, r)| Key(&l.0) == Key(&r.0) && Key(&l.1) == Key(&r.1))
+        };
+        match (self.0, other.0) {
+            (Node::Float(l), Node::Float(r)) => l.to_bits() == r.to_bits(),
+            (Node::Some(l), Node::Some(r)) => Key(l) == Key(r),
+            (Node::Seq(l), Node::Seq(r)) => {
+                l.len() == r.len() && l.iter().zip(r).all(|(l, r)| Key(l) == Key(r))
+            }
+            (Node::Map(l), Node::Map(r)) => pairs(l, r),
+            (Node::Struct(l), Node::Struct(r)) => {
+                l.len() == r.len()
+                    && l.iter()
+                        .zip(r)
+                        .all(|(l, r)| l.0 == r.0 && Key(&l.1) == Key(&r.1))
+            }
+            (Node::Variant(l_name, l), Node::Variant(r_name, r)) => {
+                l_name == r_name && Key(l) == Key(r)
+            }
+            (l, r) => l == r,
+        }
+    }
+}
+
+impl Eq for Key<'_> {}
+
+impl Hash for Key<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self.0).hash(state);
+        match self.0 {
+            Node::Unit | Node::None => {}
+            Node::Bool(val) => val.hash(state),
+            Node::Int(val) => val.hash(state),
+            Node::UInt(val) => val.hash(state),
+            Node::Float(val) => val.to_bits().hash(state),
+            Node::Char(val) => val.hash(state),
+            Node::Str(val) => val.hash(state),
+            Node::Bytes(val) => val.hash(state),
+            Node::Some(inner) => Key(inner).hash(state),
+            Node::Seq(items) => items.iter().for_each(|item| Key(item).hash(state)),
+            Node::Map(entries) => entries.iter().for_each(|(key, value)| {
+                Key(key).hash(state);
+                Key(value).hash(state);
+            }),
+            Node::Struct(fields) => fields.iter().for_each(|(name, value)| {
+                name.hash(state);
+                Key(value).hash(state);
+            }),
+            Node::Variant(name, inner) => {
+                name.hash(state);
+                Key(inner).hash(state);
+            }
+        }
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{path}.{name}")
+    }
+}
+
+#[derive(Debug)]
+struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+struct NodeSerializer;
+
+impl ser::Serializer for NodeSerializer {
+    type Ok = Node;
+    type Error = Error;
+    type SerializeSeq = SeqBuilder;
+    type SerializeTuple = SeqBuilder;
+    type SerializeTupleStruct = SeqBuilder;
+    type SerializeTupleVariant = SeqBuilder;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = StructBuilder;
+    type SerializeStructVariant = StructBuilder;
+
+    fn serialize_bool(self, v: bool) -> Result<Node, Error> {
+        Ok(Node::Bool(v))
+    }
+    fn serialize_i8(self, v: i8) -> Result<Node, Error> {
+        Ok(Node::Int(v.into()))
+    }
+    fn serialize_i16(self, v: i16) -> Result<Node, Error> {
+        Ok(Node::Int(v.into()))
+    }
+    fn serialize_i32(self, v: i32) -> Result<Node, Error> {
+        Ok(Node::Int(v.into()))
+    }
+    fn serialize_i64(self, v: i64) -> Result<Node, Error> {
+        Ok(Node::Int(v.into()))
+    }
+    fn serialize_i128(self, v: i128) -> Result<Node, Error> {
+        Ok(Node::Int(v))
+    }
+    fn serialize_u8(self, v: u8) -> Result<Node, Error> {
+        Ok(Node::UInt(v.into()))
+    }
+    fn serialize_u16(self, v: u16) -> Result<Node, Error> {
+        Ok(Node::UInt(v.into()))
+    }
+    fn serialize_u32(self, v: u32) -> Result<Node, Error> {
+        Ok(Node::UInt(v.into()))
+    }
+    fn serialize_u64(self, v: u64) -> Result<Node, Error> {
+        Ok(Node::UInt(v.into()))
+    }
+    fn serialize_u128(self, v: u128) -> Result<Node, Error> {
+        Ok(Node::UInt(v))
+    }
+    fn serialize_f32(self, v: f32) -> Result<Node, Error> {
+        Ok(Node::Float(v.into()))
+    }
+    fn serialize_f64(self, v: f64) -> Result<Node, Error> {
+        Ok(Node::Float(v))
+    }
+    fn serialize_char(self, v: char) -> Result<Node, Error> {
+        Ok(Node::Char(v))
+    }
+    fn serialize_str(self, v: &str) -> Result<Node, Error> {
+        Ok(Node::Str(v.to_string()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Node, Error> {
+        Ok(Node::Bytes(v.to_vec()))
+    }
+    fn serialize_none(self) -> Result<Node, Error> {
+        Ok(Node::None)
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Node, Error> {
+        Ok(Node::Some(Box::new(value.serialize(self)?)))
+    }
+    fn serialize_unit(self) -> Result<Node, Error> {
+        Ok(Node::Unit)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Node, Error> {
+        Ok(Node::Unit)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Node, Error> {
+        Ok(Node::Variant(variant, Box::new(Node::Unit)))
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Node, Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Node, Error> {
+        Ok(Node::Variant(variant, Box::new(value.serialize(self)?)))
+    }
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqBuilder, Error> {
+        Ok(SeqBuilder {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or_default()),
+        })
+    }
+    fn serialize_tuple(self, len: usize) -> Result<SeqBuilder, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqBuilder, Error> {
+        self.serialize_seq(Some(len))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqBuilder, Error> {
+        Ok(SeqBuilder {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+    fn serialize_map(self, len: Option<usize>) -> Result<MapBuilder, Error> {
+        Ok(MapBuilder {
+            entries: Vec::with_capacity(len.unwrap_or_default()),
+            key: None,
+        })
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<StructBuilder, Error> {
+        Ok(StructBuilder {
+            variant: None,
+            fields: Vec::with_capacity(len),
+        })
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<StructBuilder, Error> {
+        Ok(StructBuilder {
+            variant: Some(variant),
+            fields: Vec::with_capacity(len),
+        })
+    }
+}
+
+/// Sequences, tuples and tuple structs, and tuple variants if `variant` is set.
+struct SeqBuilder {
+    variant: Option<&'static str>,
+    items: Vec<Node>,
+}
+
+impl SeqBuilder {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(value.serialize(NodeSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Node, Error> {
+        let seq = Node::Seq(self.items);
+        Ok(match self.variant {
+            Some(variant) => Node::Variant(variant, Box::new(seq)),
+            None => seq,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqBuilder {
+    type Ok = Node;
+    type Error = Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Node, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqBuilder {
+    type Ok = Node;
+    type Error = Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Node, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqBuilder {
+    type Ok = Node;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Node, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqBuilder {
+    type Ok = Node;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Node, Error> {
+        self.finish()
+    }
+}
+
+struct MapBuilder {
+    entries: Vec<(Node, Node)>,
+    key: Option<Node>,
+}
+
+impl ser::SerializeMap for MapBuilder {
+    type Ok = Node;
+    type Error = Error;
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(NodeSerializer)?);
+        Ok(())
+    }
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error("map value without a key".to_string()))?;
+        self.entries.push((key, value.serialize(NodeSerializer)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Node, Error> {
+        Ok(Node::Map(self.entries))
+    }
+}
+
+/// Structs, and struct variants if `variant` is set.
+struct StructBuilder {
+    variant: Option<&'static str>,
+    fields: Vec<(&'static str, Node)>,
+}
+
+impl StructBuilder {
+    fn push<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        self.fields.push((key, value.serialize(NodeSerializer)?));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Node, Error> {
+        let fields = Node::Struct(self.fields);
+        Ok(match self.variant {
+            Some(variant) => Node::Variant(variant, Box::new(fields)),
+            None => fields,
+        })
+    }
+}
+
+impl ser::SerializeStruct for StructBuilder {
+    type Ok = Node;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push(key, value)
+    }
+    fn end(self) -> Result<Node, Error> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for StructBuilder {
+    type Ok = Node;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.push(key, value)
+    }
+    fn end(self) -> Result<Node, Error> {
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Outer {
+        inner: BTreeMap<String, Vec<Entry>>,
+    }
+
+    #[derive(Serialize)]
+    struct Entry {
+        count: u32,
+    }
+
+    fn outer(entries: &[(&str, &[u32])]) -> Outer {
+        Outer {
+            inner: entries
+                .iter()
+                .map(|(key, counts)| {
+                    let counts = counts.iter().map(|&count| Entry { count }).collect();
+                    (key.to_string(), counts)
+                })
+                .collect(),
+        }
+    }
+
+    fn diff(orig: &Outer, deser: &Outer) -> Vec<String> {
+        orig.diff(deser, FloatCmp::PartialEq)
+    }
+
+    #[test]
+    fn equal_values_have_no_diff() {
+        let value = outer(&[("a", &[1, 2])]);
+        assert!(diff(&value, &value).is_empty());
+    }
+
+    #[test]
+    fn paths_into_a_nested_map_and_seq() {
+        assert_eq!(
+            diff(&outer(&[("a", &[1, 2])]), &outer(&[("a", &[1, 3])])),
+            [r#"inner["a"][1].count: 2 != 3"#]
+        );
+        assert_eq!(
+            diff(&outer(&[("a", &[1, 2])]), &outer(&[("a", &[1])])),
+            [r#"inner["a"]: 2 items != 1 items"#]
+        );
+    }
+
+    #[test]
+    fn map_entries_missing_on_either_side() {
+        assert_eq!(
+            diff(&outer(&[("a", &[1])]), &outer(&[("b", &[1])])),
+            [
+                r#"inner["a"]: [1 items] != missing"#,
+                r#"inner["b"]: missing != [1 items]"#
+            ]
+        );
+    }
+
+    #[test]
+    fn floats_by_float_cmp() {
+        assert!((-0.0f64).diff(&0.0, FloatCmp::PartialEq).is_empty());
+        assert_eq!(
+            (-0.0f64).diff(&0.0, FloatCmp::Bitwise),
+            ["<root>: -0.0 != 0.0"]
+        );
+        assert!(f64::NAN.diff(&f64::NAN, FloatCmp::Bitwise).is_empty());
+    }
+}
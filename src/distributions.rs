@@ -0,0 +1,75 @@
+//! Samplers for the seeded test data generators. `rand` 0.8 only comes with uniform ones.
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Seed of the generated scenarios, so every run serializes the same data.
+pub const SEED: u64 = 0x5eed_da7a;
+
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+/// Box-Muller transform.
+pub fn standard_normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+pub fn lognormal(rng: &mut StdRng, mu: f64, sigma: f64) -> f64 {
+    (mu + sigma * standard_normal(rng)).exp()
+}
+
+/// Inverse CDF, `1 - u` is never 0.
+pub fn pareto(rng: &mut StdRng, scale: f64, shape: f64) -> f64 {
+    scale / (1.0 - rng.gen::<f64>()).powf(1.0 / shape)
+}
+
+/// A document count, most are small, a few are huge.
+pub fn doc_count(rng: &mut StdRng) -> u64 {
+    lognormal(rng, 5.0, 2.0) as u64 + 1
+}
+
+/// An alphanumeric term like the ones in the term aggregation data.
+pub fn term(rng: &mut StdRng) -> String {
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    let len = rng.gen_range(6..=24);
+    (0..len)
+        .map(|_| CHARS[rng.gen_range(0..CHARS.len())] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(seed: u64) -> Vec<(u64, String, f64)> {
+        let mut rng = seeded_rng(seed);
+        (0..100)
+            .map(|_| {
+                (
+                    doc_count(&mut rng),
+                    term(&mut rng),
+                    pareto(&mut rng, 1.0, 1.5),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_are_seeded() {
+        assert_eq!(samples(SEED), samples(SEED));
+        assert_ne!(samples(SEED), samples(SEED + 1));
+    }
+
+    #[test]
+    fn samples_are_in_range() {
+        let mut rng = seeded_rng(SEED);
+        for _ in 0..1000 {
+            assert!(doc_count(&mut rng) >= 1);
+            assert!((6..=24).contains(&term(&mut rng).len()));
+            assert!(pareto(&mut rng, 2.0, 1.5) >= 2.0);
+            assert!(lognormal(&mut rng, 0.0, 1.0) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! Decodes hostile payloads that claim far more data than they have, the way a bad peer could
+//! send them: length prefixes of 2^40 or 2^32-1 elements, or of a 64 MiB string followed by one
+//! byte.
+//!
+//! A decoder that reserves memory for the claimed length before reading it fails the allocation,
+//! which aborts the process. So every attack runs in a child process, which reports how long
+//! the decoder took to fail and, with the `alloc-counter` feature, how much memory it allocated.
+
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use prettytable::{cell, Row};
+
+use crate::{
+    alloc_counter,
+    cli::Cli,
+    error_result, first_line,
+    formats::{self, Deserializes, FormatVisitor, Formats, RawBytes, Serializes},
+    get_table,
+    panic_guard::catch_panic,
+    subprocess,
+    test_struct_agg2::{single_term, IntermediateTermBucketResult, Store},
+    test_struct_hostile::{ClaimedMap, ClaimedSeq, HostileStore, HostileTerms},
+    SKIPPED,
+};
+
+/// Elements claimed by the sequence attack.
+const CLAIMED_LEN: u64 = 1 << 40;
+/// The longest length MessagePack and Speedy can claim, they have 32 bit length prefixes.
+const CLAIMED_LEN_32: u64 = u32::MAX as u64;
+/// Bytes of the string attack. The string is really built and serialized, then cut off.
+const CLAIMED_STRING_BYTES: usize = 64 * 1024 * 1024;
+
+/// Prefixes of the lines the child reports on.
+const RESULT_PREFIX: &str = "result: ";
+const TIME_PREFIX: &str = "time_ns: ";
+const PEAK_PREFIX: &str = "peak_bytes: ";
+
+/// A hostile payload, decoded as the type it imitates.
+struct Attack {
+    name: &'static str,
+    /// Crafts the payload for a format and decodes it with a memory limit in bytes, in the child
+    /// process.
+    run: fn(format: &str, limit: usize) -> Result<Decoded>,
+}
+
+/// How a payload lies about its size.
+#[derive(Clone, Copy)]
+enum Claim {
+    /// A length prefix of this many elements. Formats whose prefixes can't hold it, or that have
+    /// none, are n/a rather than being sent a smaller claim.
+    Len(u64),
+    /// A string cut off after its first byte, so its length prefix claims the rest.
+    CutString,
+}
+
+fn attacks() -> Vec<Attack> {
+    vec![
+        Attack {
+            name: "Store::bins of 2^40 u64",
+            run: |format, limit| {
+                let store = HostileStore {
+                    bins: ClaimedSeq {
+                        len: CLAIMED_LEN,
+                        items: vec![1, 2, 3],
+                    },
+                };
+                decode_with::<_, Store>(format, store, Claim::Len(CLAIMED_LEN), limit)
+            },
+        },
+        Attack {
+            name: "Store::bins of 2^32-1 u64",
+            run: |format, limit| {
+                let store = HostileStore {
+                    bins: ClaimedSeq {
+                        len: CLAIMED_LEN_32,
+                        items: vec![1, 2, 3],
+                    },
+                };
+                decode_with::<_, Store>(format, store, Claim::Len(CLAIMED_LEN_32), limit)
+            },
+        },
+        Attack {
+            name: "Term entries of 2^32-1",
+            run: |format, limit| {
+                let terms = HostileTerms {
+                    entries: ClaimedMap {
+                        len: CLAIMED_LEN_32,
+                        entries: Vec::new(),
+                    },
+                    sum_other_doc_count: 0,
+                    doc_count_error_upper_bound: 0,
+                };
+                let claim = Claim::Len(CLAIMED_LEN_32);
+                decode_with::<_, IntermediateTermBucketResult>(format, terms, claim, limit)
+            },
+        },
+        Attack {
+            name: "Term key of 64 MiB",
+            run: |format, limit| {
+                let terms = single_term("x".repeat(CLAIMED_STRING_BYTES));
+                let claim = Claim::CutString;
+                decode_with::<_, IntermediateTermBucketResult>(format, terms, claim, limit)
+            },
+        },
+    ]
+}
+
+/// What the decoder did with a payload it survived.
+struct Decoded {
+    result: String,
+    /// `None` if the child aborted.
+    time_ns: Option<u128>,
+    peak_bytes: Option<usize>,
+}
+
+impl Decoded {
+    /// The lines the child prints, read back by [`Decoded::parse`].
+    fn report(&self) -> String {
+        let mut report = format!("{RESULT_PREFIX}{}\n", first_line(&self.result));
+        if let Some(time) = self.time_ns {
+            report += &format!("{TIME_PREFIX}{time}\n");
+        }
+        if let Some(peak) = self.peak_bytes {
+            report += &format!("{PEAK_PREFIX}{peak}\n");
+        }
+        report
+    }
+
+    /// Reads the report of a child, `None` if it has no result line.
+    fn parse(stdout: &str) -> Option<Decoded> {
+        let line = |prefix: &str| {
+            stdout
+                .lines()
+                .find_map(|line| line.strip_prefix(prefix))
+                .map(str::to_string)
+        };
+        Some(Decoded {
+            result: line(RESULT_PREFIX)?,
+            time_ns: line(TIME_PREFIX).and_then(|time| time.parse().ok()),
+            peak_bytes: line(PEAK_PREFIX).and_then(|peak| peak.parse().ok()),
+        })
+    }
+}
+
+/// Crafts the payload for one format. A crafting error is a bug in the attack, not a result.
+type CraftFn<W> = fn(W, Claim, usize) -> Result<Decoded>;
+
+/// Makes the [`CraftFn`] of a format.
+struct Craft;
+
+impl<W, R> FormatVisitor<W, R> for Craft {
+    type Output = CraftFn<W>;
+    fn visit<F: Serializes<W> + Deserializes<R>>(&self) -> CraftFn<W> {
+        decode::<W, R, F>
+    }
+}
+
+fn decode_with<W: Formats<R>, R>(
+    format: &str,
+    value: W,
+    claim: Claim,
+    limit: usize,
+) -> Result<Decoded> {
+    let Some((_, Some(craft))) = formats::list_pair::<W, R, _>(&Craft)
+        .into_iter()
+        .find(|(name, _)| name == format)
+    else {
+        bail!("unknown format {format}");
+    };
+    craft(value, claim, limit)
+}
+
+/// Serializes `value` with `F` and decodes it as `R`. `limit` only applies to decoding, crafting
+/// the string attack needs more.
+fn decode<W, R, F: Serializes<W> + Deserializes<R>>(
+    value: W,
+    claim: Claim,
+    limit: usize,
+) -> Result<Decoded> {
+    if let Claim::Len(len) = claim {
+        if F::max_claimed_len().is_none_or(|max| max < len) {
+            return Ok(Decoded {
+                result: SKIPPED.to_string(),
+                time_ns: None,
+                peak_bytes: None,
+            });
+        }
+    }
+    let payload = craft::<W, F>(value, claim)?;
+
+    alloc_counter::set_limit(limit);
+    let start = Instant::now();
+    let (res, allocs) =
+        alloc_counter::measure(|| catch_panic(|| <F as Deserializes<R>>::deserialize(payload)));
+    let time_ns = start.elapsed().as_nanos();
+    let result = match res {
+        Ok(Ok(_)) => "Decoded".to_string(),
+        Ok(Err(err)) => error_result::<F>("Rejected", &err),
+        Err(panic) => format!("Panic: {panic}"),
+    };
+    Ok(Decoded {
+        result,
+        time_ns: Some(time_ns),
+        peak_bytes: allocs.map(|allocs| allocs.peak_live_bytes),
+    })
+}
+
+/// Serializes `value` with `F`. For [`Claim::CutString`] the payload ends after the first byte
+/// of the long string.
+fn craft<W, F: Serializes<W>>(value: W, claim: Claim) -> Result<F::Serialized> {
+    let (_, serialized) = F::serialize(&value).context("could not craft the payload")?;
+    drop(value);
+    let mut payload = serialized.to_raw();
+    if let Claim::CutString = claim {
+        let start = payload
+            .windows(8)
+            .position(|window| window == b"xxxxxxxx")
+            .context("could not find the string in the payload")?;
+        payload.truncate(start + 1);
+        payload.shrink_to_fit();
+    }
+    Ok(F::Serialized::from_raw(payload))
+}
+
+/// Entry point of the child process, runs `attack` against `format`.
+pub fn run_child(cli: &Cli, format: &str, attack: &str) -> Result<()> {
+    let Some(attack) = attacks()
+        .into_iter()
+        .find(|candidate| candidate.name == attack)
+    else {
+        bail!("unknown attack {attack}");
+    };
+    let decoded = (attack.run)(format, cli.memory_limit * 1024 * 1024)?;
+    print!("{}", decoded.report());
+    Ok(())
+}
+
+/// Runs one attack against one format in a child process.
+fn run_attack(cli: &Cli, format: &str, attack: &str) -> Result<Decoded> {
+    // The child takes --dos too, --memory-limit requires it.
+    let output = subprocess::run_self(&[
+        "--dos".to_string(),
+        "--dos-child".to_string(),
+        format.to_string(),
+        "--dos-attack".to_string(),
+        attack.to_string(),
+        "--memory-limit".to_string(),
+        cli.memory_limit.to_string(),
+    ])?;
+    if let Some(result) = output.crash() {
+        return Ok(Decoded {
+            result,
+            time_ns: None,
+            peak_bytes: None,
+        });
+    }
+    Decoded::parse(&output.stdout)
+        .with_context(|| format!("no result from the child for {format}: {}", output.stderr))
+}
+
+/// Runs every attack against every selected format and prints the outcomes.
+pub fn run(cli: &Cli) -> Result<()> {
+    let formats: Vec<String> = formats::names()
+        .into_iter()
+        .filter(|format| cli.runs_format(format))
+        .collect();
+    if formats.is_empty() {
+        bail!("no format matches {:?}, see --list", cli.formats);
+    }
+
+    let mut table = get_table(cli.output);
+    let mut row = Row::empty();
+    row.add_cell(cell!["Attack"]);
+    row.add_cell(cell!["Format"]);
+    row.add_cell(cell!["Result"]);
+    row.add_cell(cell!["Time [ns]"]);
+    row.add_cell(cell!["Peak Alloc [bytes]"]);
+    table.set_titles(row);
+    for attack in attacks() {
+        for format in &formats {
+            eprintln!("{} with {format}", attack.name);
+            let decoded = run_attack(cli, format, attack.name)?;
+            let mut row = Row::empty();
+            row.add_cell(cell!(attack.name));
+            row.add_cell(cell!(format));
+            row.add_cell(cell!(decoded.result));
+            row.add_cell(cell!(decoded
+                .time_ns
+                .map(|time| time.to_string())
+                .unwrap_or_default()));
+            row.add_cell(cell!(decoded
+                .peak_bytes
+                .map(|peak| peak.to_string())
+                .unwrap_or_default()));
+            table.add_row(row);
+        }
+    }
+    println!(
+        "Hostile payloads, memory limit {} MiB{}",
+        cli.memory_limit,
+        if alloc_counter::ENABLED {
+            ""
+        } else {
+            " (only enforced with --features alloc-counter)"
+        }
+    );
+    table.printstd();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::{Bincode, Rmp};
+
+    fn claimed_store(len: u64) -> HostileStore {
+        HostileStore {
+            bins: ClaimedSeq {
+                len,
+                items: vec![1, 2, 3],
+            },
+        }
+    }
+
+    #[test]
+    fn messagepack_header_carries_the_claim() {
+        let payload =
+            craft::<_, Rmp>(claimed_store(CLAIMED_LEN_32), Claim::Len(CLAIMED_LEN_32)).unwrap();
+        // The struct is an array of one field, the bins an array32 of 2^32-1 elements.
+        assert_eq!(payload[..6], [0x91, 0xdd, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(payload[6..], [1, 2, 3]);
+    }
+
+    #[test]
+    fn bincode_header_carries_the_claim() {
+        let payload =
+            craft::<_, Bincode>(claimed_store(CLAIMED_LEN), Claim::Len(CLAIMED_LEN)).unwrap();
+        assert_eq!(payload[..8], CLAIMED_LEN.to_le_bytes());
+    }
+
+    #[test]
+    fn claims_a_format_cant_write_are_not_applicable() {
+        let decode = |format, len| {
+            decode_with::<_, Store>(format, claimed_store(len), Claim::Len(len), usize::MAX)
+                .unwrap()
+                .result
+        };
+        assert_eq!(decode("MessagePack", CLAIMED_LEN), SKIPPED);
+        assert_eq!(decode("Speedy", CLAIMED_LEN), SKIPPED);
+        assert_eq!(decode("Json", CLAIMED_LEN_32), SKIPPED);
+        assert_eq!(decode("BSON", CLAIMED_LEN_32), SKIPPED);
+        assert!(decode("MessagePack", CLAIMED_LEN_32).starts_with("Rejected"));
+        assert!(decode("Bincode", CLAIMED_LEN).starts_with("Rejected"));
+    }
+
+    #[test]
+    fn cut_string_ends_after_one_byte() {
+        let terms = single_term("x".repeat(64));
+        let payload = craft::<_, Rmp>(terms, Claim::CutString).unwrap();
+        assert_eq!(payload.last(), Some(&b'x'));
+        assert_eq!(payload.iter().filter(|&&byte| byte == b'x').count(), 1);
+    }
+
+    #[test]
+    fn report_parses_back() {
+        let decoded = Decoded {
+            result: "Rejected: too long\nsecond line".to_string(),
+            time_ns: Some(42),
+            peak_bytes: Some(1024),
+        };
+        let parsed = Decoded::parse(&decoded.report()).unwrap();
+        assert_eq!(parsed.result, "Rejected: too long");
+        assert_eq!(parsed.time_ns, Some(42));
+        assert_eq!(parsed.peak_bytes, Some(1024));
+
+        let minimal = Decoded::parse("result: n/a\n").unwrap();
+        assert_eq!(minimal.result, "n/a");
+        assert_eq!(minimal.time_ns, None);
+        assert!(Decoded::parse("memory allocation of 8 bytes failed").is_none());
+    }
+}
@@ -0,0 +1,168 @@
+//! Machine-readable JSON and CSV output of the results.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{alloc_counter, cli::Cli, FormatResult, ScenarioResult};
+
+/// Describes the run the results come from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// Start of the run in seconds since the unix epoch.
+    pub timestamp: u64,
+    pub version: String,
+    pub warmup: usize,
+    pub iterations: usize,
+    pub alloc_counter: bool,
+    pub os: String,
+    pub arch: String,
+}
+
+impl RunMetadata {
+    pub fn new(cli: &Cli) -> Self {
+        let config = cli.bench_config();
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            warmup: config.warmup,
+            iterations: config.iterations,
+            alloc_counter: alloc_counter::ENABLED,
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+        }
+    }
+}
+
+/// Results keyed by scenario name and then by format name.
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    metadata: &'a RunMetadata,
+    results: BTreeMap<&'a str, BTreeMap<&'a str, &'a FormatResult>>,
+}
+
+pub fn write_json(
+    out: impl Write,
+    metadata: &RunMetadata,
+    scenarios: &[ScenarioResult],
+) -> Result<()> {
+    let results = scenarios
+        .iter()
+        .map(|scenario| {
+            let formats = scenario
+                .results
+                .iter()
+                .map(|res| (res.format.as_str(), res))
+                .collect();
+            (scenario.name.as_str(), formats)
+        })
+        .collect();
+    serde_json::to_writer_pretty(out, &JsonExport { metadata, results })?;
+    Ok(())
+}
+
+/// One flat CSV line, the raw samples are only part of the JSON output.
+#[derive(Serialize)]
+struct CsvRecord<'a> {
+    scenario: &'a str,
+    format: &'a str,
+    result: &'a str,
+    /// The mismatches of a "Cmp Mismatch", separated by "; ".
+    mismatches: String,
+    serialized_size: usize,
+    serialize_min_ns: u128,
+    serialize_median_ns: u128,
+    serialize_mean_ns: u128,
+    serialize_p99_ns: u128,
+    serialize_ci_low_ns: u128,
+    serialize_ci_high_ns: u128,
+    deserialize_min_ns: u128,
+    deserialize_median_ns: u128,
+    deserialize_mean_ns: u128,
+    deserialize_p99_ns: u128,
+    deserialize_ci_low_ns: u128,
+    deserialize_ci_high_ns: u128,
+    roundtrip_median_ns: u128,
+    roundtrip_ci_low_ns: u128,
+    roundtrip_ci_high_ns: u128,
+    roundtrip_outliers: usize,
+    serialize_mb_per_sec: f64,
+    deserialize_mb_per_sec: f64,
+    serialize_objects_per_sec: f64,
+    deserialize_objects_per_sec: f64,
+    serialize_allocations: Option<usize>,
+    serialize_allocated_bytes: Option<usize>,
+    serialize_peak_live_bytes: Option<usize>,
+    deserialize_allocations: Option<usize>,
+    deserialize_allocated_bytes: Option<usize>,
+    deserialize_peak_live_bytes: Option<usize>,
+    timestamp: u64,
+    version: &'a str,
+    warmup: usize,
+    iterations: usize,
+    os: &'a str,
+    arch: &'a str,
+}
+
+pub fn write_csv(
+    out: impl Write,
+    metadata: &RunMetadata,
+    scenarios: &[ScenarioResult],
+) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(out);
+    for scenario in scenarios {
+        for res in &scenario.results {
+            let (ser, deser, roundtrip) =
+                (res.serialize_time, res.deserialize_time, res.roundtrip_time);
+            writer.serialize(CsvRecord {
+                scenario: &scenario.name,
+                format: &res.format,
+                result: &res.result,
+                mismatches: res.mismatches.join("; "),
+                serialized_size: res.serialized_size,
+                serialize_min_ns: ser.min,
+                serialize_median_ns: ser.median,
+                serialize_mean_ns: ser.mean,
+                serialize_p99_ns: ser.p99,
+                serialize_ci_low_ns: ser.ci_low,
+                serialize_ci_high_ns: ser.ci_high,
+                deserialize_min_ns: deser.min,
+                deserialize_median_ns: deser.median,
+                deserialize_mean_ns: deser.mean,
+                deserialize_p99_ns: deser.p99,
+                deserialize_ci_low_ns: deser.ci_low,
+                deserialize_ci_high_ns: deser.ci_high,
+                roundtrip_median_ns: roundtrip.median,
+                roundtrip_ci_low_ns: roundtrip.ci_low,
+                roundtrip_ci_high_ns: roundtrip.ci_high,
+                roundtrip_outliers: roundtrip.outliers,
+                serialize_mb_per_sec: ser.megabytes_per_sec(res.serialized_size),
+                deserialize_mb_per_sec: deser.megabytes_per_sec(res.serialized_size),
+                serialize_objects_per_sec: ser.ops_per_sec(),
+                deserialize_objects_per_sec: deser.ops_per_sec(),
+                serialize_allocations: res.serialize_allocs.map(|a| a.allocations),
+                serialize_allocated_bytes: res.serialize_allocs.map(|a| a.allocated_bytes),
+                serialize_peak_live_bytes: res.serialize_allocs.map(|a| a.peak_live_bytes),
+                deserialize_allocations: res.deserialize_allocs.map(|a| a.allocations),
+                deserialize_allocated_bytes: res.deserialize_allocs.map(|a| a.allocated_bytes),
+                deserialize_peak_live_bytes: res.deserialize_allocs.map(|a| a.peak_live_bytes),
+                timestamp: metadata.timestamp,
+                version: &metadata.version,
+                warmup: metadata.warmup,
+                iterations: metadata.iterations,
+                os: &metadata.os,
+                arch: &metadata.arch,
+            })?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -1,20 +1,183 @@
 use core::fmt;
 
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use anyhow::Result;
 
+use crate::{
+    any_probe,
+    bson_root::{self, Root},
+};
+
 pub use speedy::LittleEndian as SpeedyLE;
 pub use speedy::Readable as SpeedyR;
 pub use speedy::Writable as SpeedyW;
 
+pub use minicbor::Decode as MinicborD;
+pub use minicbor::Encode as MinicborE;
+
+/// A format, what it can encode is up to [`Serializes`] and [`Deserializes`].
 pub trait Deser {
-    type Serialized: fmt::Debug;
+    type Serialized: fmt::Debug + Clone + Send + RawBytes;
     fn name() -> String;
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)>;
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T>;
+    /// Whether `err` means the format can't handle the type at all, e.g. because it would need
+    /// `deserialize_any`, rather than a bug in the format.
+    fn is_unsupported(_err: &anyhow::Error) -> bool {
+        false
+    }
+    /// The most elements a length prefix of the format can claim, `None` if it has no length
+    /// prefixes, like the text formats.
+    fn max_claimed_len() -> Option<u64> {
+        Some(u64::MAX)
+    }
+}
+
+/// Implemented by a format for every type it can write, serde formats for all `Serialize` types.
+pub trait Serializes<T>: Deser {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)>;
+}
+
+/// Implemented by a format for every type it can read.
+pub trait Deserializes<T>: Deser {
+    /// Decodes an owned `T`, `s` is dropped on return. So `T` must decode from input of any
+    /// lifetime, types that borrow from the input don't qualify.
+    fn deserialize(s: Self::Serialized) -> Result<T>;
+}
+
+/// What is done with each format, e.g. benchmarking it. Writes `W` and reads `R`, which are the
+/// same type except for tests of changed types.
+pub trait FormatVisitor<W, R = W> {
+    type Output;
+    fn visit<F: Serializes<W> + Deserializes<R>>(&self) -> Self::Output;
+}
+
+/// Every format by name, in the order they are run and listed. `None` if it can't handle the
+/// type, those are skipped.
+pub type FormatList<O> = Vec<(String, Option<O>)>;
+
+/// A type the formats are tested with, written as `Self` and read back as `R`. Names the formats
+/// whose [`Serializes`] and [`Deserializes`] impls cover it, see [`impl_formats`].
+pub trait Formats<R = Self>: Sized {
+    /// Visits each format that supports the type.
+    fn visit_formats<V: FormatVisitor<Self, R>>(visitor: &V) -> Vec<(String, V::Output)>;
+}
+
+/// Implements [`Formats`] for a type with the formats it supports, e.g.
+/// `impl_formats!(Type: serde, Minicbor, Speedy)`, where `serde` stands for [`serde_formats`].
+/// A type read back as another type is given as `impl_formats!(Written => Read: serde)`.
+macro_rules! impl_formats {
+    ($ty:ty $(=> $read:ty)?: $($format:ident),+) => {
+        impl $crate::formats::Formats$(<$read>)? for $ty {
+            fn visit_formats<V: $crate::formats::FormatVisitor<Self $(, $read)?>>(
+                visitor: &V,
+            ) -> Vec<(String, V::Output)> {
+                let mut formats = Vec::new();
+                $($crate::formats::impl_formats!(@visit formats, visitor, $format);)+
+                formats
+            }
+        }
+    };
+    (@visit $formats:ident, $visitor:ident, serde) => {
+        $formats.extend($crate::formats::serde_formats($visitor));
+    };
+    (@visit $formats:ident, $visitor:ident, $format:ident) => {
+        $formats.push((
+            <$crate::formats::$format as $crate::formats::Deser>::name(),
+            $visitor.visit::<$crate::formats::$format>(),
+        ));
+    };
+}
+pub(crate) use impl_formats;
+
+/// The names of all formats, in the order they are run and listed. A new format goes here, and
+/// into [`serde_formats`] if it goes through serde.
+pub fn names() -> Vec<String> {
+    vec![
+        Json::name(),
+        Ron::name(),
+        Bincode::name(),
+        Bson::name(),
+        Bitcode::name(),
+        Rmp::name(),
+        Postcard::name(),
+        Ciborium::name(),
+        Minicbor::name(),
+        Speedy::name(),
+    ]
+}
+
+/// Visits the serde formats, they handle every serde type.
+pub fn serde_formats<W: Serialize, R: DeserializeOwned, V: FormatVisitor<W, R>>(
+    visitor: &V,
+) -> Vec<(String, V::Output)> {
+    vec![
+        (Json::name(), visitor.visit::<Json>()),
+        (Ron::name(), visitor.visit::<Ron>()),
+        (Bincode::name(), visitor.visit::<Bincode>()),
+        (Bson::name(), visitor.visit::<Bson>()),
+        (Bitcode::name(), visitor.visit::<Bitcode>()),
+        (Rmp::name(), visitor.visit::<Rmp>()),
+        (Postcard::name(), visitor.visit::<Postcard>()),
+        (Ciborium::name(), visitor.visit::<Ciborium>()),
+    ]
+}
+
+/// Visits every format that supports `T`, the others are `None`.
+pub fn list<T: Formats, V: FormatVisitor<T>>(visitor: &V) -> FormatList<V::Output> {
+    list_pair(visitor)
+}
+
+/// Like [`list`], for writing `W` and reading `R`.
+pub fn list_pair<W: Formats<R>, R, V: FormatVisitor<W, R>>(visitor: &V) -> FormatList<V::Output> {
+    let mut supported = W::visit_formats(visitor);
+    let formats = names()
+        .into_iter()
+        .map(|name| {
+            let output = supported
+                .iter()
+                .position(|(format, _)| *format == name)
+                .map(|i| supported.swap_remove(i).1);
+            (name, output)
+        })
+        .collect();
+    debug_assert!(
+        supported.is_empty(),
+        "formats missing from names(): {:?}",
+        supported.iter().map(|(name, _)| name).collect::<Vec<_>>()
+    );
+    formats
+}
+
+/// Whether `message` is the error of serde's default `serialize_i128`/`u128` and
+/// `deserialize_i128`/`u128`, which formats without 128 bit integers don't override.
+fn is_128_bit_unsupported(message: &str) -> bool {
+    matches!(message, "i128 is not supported" | "u128 is not supported")
+}
+
+/// Serialized data as plain bytes and back, to corrupt it.
+pub trait RawBytes {
+    fn to_raw(self) -> Vec<u8>;
+    fn from_raw(bytes: Vec<u8>) -> Self;
+}
+
+impl RawBytes for Vec<u8> {
+    fn to_raw(self) -> Vec<u8> {
+        self
+    }
+    fn from_raw(bytes: Vec<u8>) -> Self {
+        bytes
+    }
+}
+
+/// Text formats can only be handed valid UTF-8, invalid sequences become replacement characters.
+impl RawBytes for String {
+    fn to_raw(self) -> Vec<u8> {
+        self.into_bytes()
+    }
+    fn from_raw(bytes: Vec<u8>) -> Self {
+        String::from_utf8(bytes)
+            .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+    }
 }
 
 pub struct Json;
@@ -23,13 +186,20 @@ impl Deser for Json {
     fn name() -> String {
         "Json".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
+    fn max_claimed_len() -> Option<u64> {
+        None
+    }
+}
+
+impl<T: Serialize> Serializes<T> for Json {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
         let output: String = serde_json::to_string(t)?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Json {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
         let deser: T = serde_json::from_str(&s)?;
         Ok(deser)
     }
@@ -41,13 +211,23 @@ impl Deser for Postcard {
     fn name() -> String {
         "Postcard".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
+    fn is_unsupported(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<postcard::Error>(),
+            Some(postcard::Error::WontImplement)
+        )
+    }
+}
+
+impl<T: Serialize> Serializes<T> for Postcard {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
         let output: Vec<u8> = postcard::to_allocvec(t)?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Postcard {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
         let deser: T = postcard::from_bytes(&s)?;
         Ok(deser)
     }
@@ -59,13 +239,26 @@ impl Deser for Ron {
     fn name() -> String {
         "RON".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
+    fn is_unsupported(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<ron::Error>(),
+            Some(ron::Error::Message(message)) if is_128_bit_unsupported(message)
+        )
+    }
+    fn max_claimed_len() -> Option<u64> {
+        None
+    }
+}
+
+impl<T: Serialize> Serializes<T> for Ron {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
         let output: String = ron::to_string(t)?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Ron {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
         let deser: T = ron::from_str(&s)?;
         Ok(deser)
     }
@@ -77,13 +270,21 @@ impl Deser for Rmp {
     fn name() -> String {
         "MessagePack".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
+    /// Arrays and maps have 32 bit length prefixes, rmp-serde truncates longer ones.
+    fn max_claimed_len() -> Option<u64> {
+        Some(u32::MAX.into())
+    }
+}
+
+impl<T: Serialize> Serializes<T> for Rmp {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
         let output: Vec<u8> = rmp_serde::to_vec(t)?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Rmp {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
         let deser: T = rmp_serde::from_slice(&s)?;
         Ok(deser)
     }
@@ -95,13 +296,23 @@ impl Deser for Bincode {
     fn name() -> String {
         "Bincode".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
+    fn is_unsupported(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<bincode::Error>().map(|err| &**err),
+            Some(bincode::ErrorKind::DeserializeAnyNotSupported)
+        )
+    }
+}
+
+impl<T: Serialize> Serializes<T> for Bincode {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
         let output = bincode::serialize(t)?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Bincode {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
         let deser: T = bincode::deserialize(&s)?;
         Ok(deser)
     }
@@ -113,73 +324,172 @@ impl Deser for Ciborium {
     fn name() -> String {
         "Ciborium".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
+}
+
+impl<T: Serialize> Serializes<T> for Ciborium {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
         let mut output: Vec<u8> = Vec::new();
-        ciborium::ser::into_writer(t, &mut output).unwrap();
+        ciborium::ser::into_writer(t, &mut output)?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Ciborium {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
         let deser: T = ciborium::de::from_reader(&mut s.as_slice())?;
         Ok(deser)
     }
 }
 
+pub struct Minicbor;
+impl Deser for Minicbor {
+    type Serialized = Vec<u8>;
+    fn name() -> String {
+        "Minicbor".to_string()
+    }
+}
+
+impl<T: MinicborE<()>> Serializes<T> for Minicbor {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
+        let output: Vec<u8> = minicbor::to_vec(t)?;
+        Ok((output.len(), output))
+    }
+}
+
+impl<T: for<'b> MinicborD<'b, ()>> Deserializes<T> for Minicbor {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
+        let deser: T = minicbor::decode(&s)?;
+        Ok(deser)
+    }
+}
+
 pub struct Bson;
 impl Deser for Bson {
-    type Serialized = bson::Bson;
+    type Serialized = Vec<u8>;
     fn name() -> String {
         "BSON".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
-        let output = bson::to_bson(t)?;
-        Ok((0, output))
+    /// Arrays are documents, they end with a null byte instead of having a length.
+    fn max_claimed_len() -> Option<u64> {
+        None
+    }
+    fn is_unsupported(err: &anyhow::Error) -> bool {
+        // BSON has no unsigned integers, no 128 bit integers and only string keys.
+        match err.downcast_ref::<bson::ser::Error>() {
+            Some(
+                bson::ser::Error::UnsignedIntegerExceededRange(_)
+                | bson::ser::Error::InvalidDocumentKey(_),
+            ) => true,
+            Some(bson::ser::Error::SerializationError { message, .. }) => {
+                is_128_bit_unsupported(message)
+            }
+            _ => false,
+        }
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
-        let deser: T = bson::from_bson(s)?;
+}
+
+impl<T: Serialize> Serializes<T> for Bson {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
+        let output = if bson_root::needs_wrapper(t) {
+            bson::to_vec(&BsonRoot { root: t })?
+        } else {
+            bson::to_vec(t)?
+        };
+        Ok((output.len(), output))
+    }
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Bson {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
+        let is_wrapped = match bson_root::root_of::<T>() {
+            Root::Wrapped => true,
+            Root::Document => bson_root::is_wrapper(bson::RawDocument::from_bytes(&s)?)?,
+        };
+        let deser: T = if is_wrapped {
+            bson::from_slice::<BsonRoot<T>>(&s)?.root
+        } else {
+            bson::from_slice(&s)?
+        };
         Ok(deser)
     }
 }
 
+/// The document non-document roots are wrapped in, its field is [`bson_root::KEY`].
+#[derive(Serialize, Deserialize)]
+struct BsonRoot<T> {
+    root: T,
+}
+
 pub struct Bitcode;
 impl Deser for Bitcode {
     type Serialized = Vec<u8>;
     fn name() -> String {
         "Bitcode".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
+    fn is_unsupported(err: &anyhow::Error) -> bool {
+        err.downcast_ref::<NotSelfDescribing>().is_some()
+    }
+}
+
+impl<T: Serialize> Serializes<T> for Bitcode {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
         let output: Vec<u8> = bitcode::serialize(t)?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
-        let deser: T = bitcode::deserialize(&s)?;
+}
+
+impl<T: DeserializeOwned> Deserializes<T> for Bitcode {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
+        // Release builds of bitcode drop the reason of an error, so a type it can't read is told
+        // by the type instead.
+        let deser: T = bitcode::deserialize(&s).map_err(|err| {
+            let err = anyhow::Error::from(err);
+            match any_probe::self_describing_call::<T>() {
+                Some(method) => err.context(NotSelfDescribing(method)),
+                None => err,
+            }
+        })?;
         Ok(deser)
     }
 }
 
+/// The error of a format that isn't self-describing, for a type that calls this method of the
+/// deserializer, e.g. `deserialize_any`.
+#[derive(Debug)]
+struct NotSelfDescribing(&'static str);
+
+impl fmt::Display for NotSelfDescribing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "needs a self-describing format, the type calls {}",
+            self.0
+        )
+    }
+}
+
 pub struct Speedy;
 impl Deser for Speedy {
     type Serialized = Vec<u8>;
     fn name() -> String {
         "Speedy".to_string()
     }
-    fn serialize<T: Serialize + SpeedyW<SpeedyLE>>(t: &T) -> Result<(usize, Self::Serialized)> {
-        let output = t.write_to_vec().unwrap();
+    /// Collections have 32 bit length prefixes.
+    fn max_claimed_len() -> Option<u64> {
+        Some(u32::MAX.into())
+    }
+}
+
+impl<T: SpeedyW<SpeedyLE>> Serializes<T> for Speedy {
+    fn serialize(t: &T) -> Result<(usize, Self::Serialized)> {
+        let output = t.write_to_vec()?;
         Ok((output.len(), output))
     }
-    fn deserialize<T: DeserializeOwned + SpeedyR<'static, SpeedyLE>>(
-        s: Self::Serialized,
-    ) -> Result<T> {
-        let deser: T = T::read_from_buffer(extend_lifetime(&s)).unwrap();
-        //let deser: T = bitcode::deserialize(&s)?;
+}
+
+impl<T: for<'b> SpeedyR<'b, SpeedyLE>> Deserializes<T> for Speedy {
+    fn deserialize(s: Self::Serialized) -> Result<T> {
+        let deser: T = T::read_from_buffer(&s)?;
         Ok(deser)
     }
 }
-fn extend_lifetime<'b>(r: &'b [u8]) -> &'static [u8] {
-    unsafe { std::mem::transmute::<&'b [u8], &'static [u8]>(r) }
-}
@@ -0,0 +1,263 @@
+//! Roundtrips random aggregation results through every format and shrinks failures to a minimal
+//! reproducer.
+
+use anyhow::{bail, Result};
+use prettytable::{cell, Row};
+use proptest::{
+    num::f64,
+    prelude::{BoxedStrategy, Strategy},
+    test_runner::{Config, RngAlgorithm, TestCaseError, TestError, TestRng, TestRunner},
+};
+use serde::Serialize;
+
+use crate::{
+    cli::{Cli, FloatCmp},
+    cmp_res,
+    diff::Diff,
+    error_result, first_line,
+    formats::{self, Deserializes, FormatList, FormatVisitor, Formats, Serializes},
+    get_table, test_struct_agg1, test_struct_agg2,
+};
+
+/// Roundtrips one value through one format.
+type CheckFn<T> = fn(&T, FloatCmp) -> Result<(), TestCaseError>;
+
+/// Every format that supports `T`.
+fn checks<T: Formats + PartialEq + Diff>() -> FormatList<CheckFn<T>> {
+    formats::list(&Check)
+}
+
+/// Makes the [`CheckFn`] of a format.
+struct Check;
+
+impl<T: PartialEq + Diff> FormatVisitor<T> for Check {
+    type Output = CheckFn<T>;
+    fn visit<F: Serializes<T> + Deserializes<T>>(&self) -> CheckFn<T> {
+        roundtrip::<T, F>
+    }
+}
+
+fn roundtrip<T: PartialEq + Diff, F: Serializes<T> + Deserializes<T>>(
+    value: &T,
+    float_cmp: FloatCmp,
+) -> Result<(), TestCaseError> {
+    let (_, serialized) = F::serialize(value)
+        .map_err(|err| TestCaseError::fail(error_result::<F>("Ser Err", &err)))?;
+    let deser: T = F::deserialize(serialized)
+        .map_err(|err| TestCaseError::fail(error_result::<F>("Deser Err", &err)))?;
+    cmp_res(value, &deser, float_cmp).map_err(|mismatches| {
+        TestCaseError::fail(format!("Cmp Mismatch\n{}", mismatches.join("\n")))
+    })
+}
+
+/// Proptest stops shrinking after 4 times the number of cases by default, which leaves large
+/// reproducers. A roundtrip is cheap, so shrinking runs until the value is minimal.
+const MAX_SHRINK_ITERS: u32 = 1_000_000;
+
+/// Any float, NaN only if `nan`.
+fn floats(nan: bool) -> BoxedStrategy<f64> {
+    if nan {
+        f64::ANY.boxed()
+    } else {
+        (f64::POSITIVE | f64::NEGATIVE | f64::NORMAL | f64::SUBNORMAL | f64::ZERO | f64::INFINITE)
+            .boxed()
+    }
+}
+
+/// Types that contain values of their own type, like aggregation results in their buckets.
+pub trait SubAggregations {
+    /// The values of the own type one level down.
+    fn sub_aggregations(&self) -> Vec<&Self>;
+}
+
+/// Replaces a failing value with a failing sub-aggregation as long as there is one. Shrinking
+/// only simplifies values in place, it can't lift e.g. a NaN in a sub-aggregation out of the
+/// bucket around it.
+fn collapse<T: SubAggregations + Clone>(
+    mut value: T,
+    mut reason: String,
+    check: CheckFn<T>,
+) -> (T, String) {
+    loop {
+        let failing = value.sub_aggregations().into_iter().find_map(|sub| {
+            match check(sub, FloatCmp::Bitwise) {
+                Err(TestCaseError::Fail(reason)) => {
+                    Some((sub.clone(), reason.message().to_string()))
+                }
+                Err(TestCaseError::Reject(_)) | Ok(()) => None,
+            }
+        });
+        let Some((sub, sub_reason)) = failing else {
+            return (value, reason);
+        };
+        (value, reason) = (sub, sub_reason);
+    }
+}
+
+/// A minimal failing value of a format.
+struct Failure {
+    reason: String,
+    reproducer: String,
+}
+
+/// Runs `cli.fuzz_cases` random values of one type through every selected format. Returns a
+/// result row per format and the failures.
+fn fuzz_type<T: Formats + PartialEq + Diff + Serialize + SubAggregations + Clone>(
+    ty: &'static str,
+    strategy: impl Strategy<Value = T>,
+    cli: &Cli,
+) -> Vec<(&'static str, String, Option<Failure>)> {
+    let mut results = Vec::new();
+    for (format, check) in checks::<T>() {
+        // Formats that can't handle `T` have nothing to fuzz.
+        let Some(check) = check.filter(|_| cli.runs_format(&format)) else {
+            continue;
+        };
+        eprintln!("Fuzzing {ty} with {format}");
+        let config = Config {
+            cases: cli.fuzz_cases,
+            max_shrink_iters: MAX_SHRINK_ITERS,
+            failure_persistence: None,
+            ..Config::default()
+        };
+        // Every format gets the same values, so failures can be compared between formats.
+        let seed: Vec<u8> = cli.fuzz_seed.to_le_bytes().repeat(4);
+        let mut runner =
+            TestRunner::new_with_rng(config, TestRng::from_seed(RngAlgorithm::ChaCha, &seed));
+        // NaN never equals itself with `PartialEq`, every format would fail on the first one.
+        let failure = match runner.run(&strategy, |value| check(&value, FloatCmp::Bitwise)) {
+            Ok(()) => None,
+            Err(TestError::Fail(reason, value)) => {
+                let (value, reason) = collapse(value, reason.message().to_string(), check);
+                Some(Failure {
+                    reason,
+                    reproducer: ron::ser::to_string_pretty(&value, Default::default())
+                        .unwrap_or_else(|err| format!("not printable as RON: {err}")),
+                })
+            }
+            Err(TestError::Abort(reason)) => Some(Failure {
+                reason: format!("Aborted: {}", reason.message()),
+                reproducer: String::new(),
+            }),
+        };
+        results.push((ty, format, failure));
+    }
+    results
+}
+
+/// Fuzzes both aggregation result types, prints a summary and the minimal reproducers, and fails
+/// if any format did.
+pub fn run(cli: &Cli) -> Result<()> {
+    let floats = floats(!cli.no_nan);
+    let mut results = fuzz_type(
+        "IntermediateAggregationResults",
+        test_struct_agg1::arb_results(floats.clone()),
+        cli,
+    );
+    results.extend(fuzz_type(
+        "IntermediateAggregationResults2",
+        test_struct_agg2::arb_results(floats),
+        cli,
+    ));
+    if results.is_empty() {
+        bail!("no format matches {:?}, see --list", cli.formats);
+    }
+
+    println!(
+        "Fuzzing: {} cases per type and format, seed {}",
+        cli.fuzz_cases, cli.fuzz_seed
+    );
+    let mut table = get_table(cli.output);
+    let mut row = Row::empty();
+    row.add_cell(cell!["Type"]);
+    row.add_cell(cell!["Format"]);
+    row.add_cell(cell!["Result"]);
+    table.set_titles(row);
+    let mut failures = Vec::new();
+    for (ty, format, failure) in results {
+        let mut row = Row::empty();
+        row.add_cell(cell!(ty));
+        row.add_cell(cell!(format));
+        match failure {
+            // The type has values the format can't represent, that is no bug to reproduce.
+            Some(failure) if failure.reason.starts_with("Unsupported") => {
+                row.add_cell(cell!(first_line(&failure.reason)));
+            }
+            Some(failure) => {
+                row.add_cell(cell!(first_line(&failure.reason)));
+                failures.push((ty, format, failure));
+            }
+            None => row.add_cell(cell!("Ok")),
+        }
+        table.add_row(row);
+    }
+    table.printstd();
+
+    for (ty, format, failure) in &failures {
+        println!();
+        println!("{} on {}: {}", format, ty, failure.reason);
+        if !failure.reproducer.is_empty() {
+            println!("Minimal reproducer:");
+            println!("{}", failure.reproducer);
+        }
+    }
+    if !failures.is_empty() {
+        bail!("{} of the fuzzed formats failed", failures.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_struct_agg1::{
+        IntermediateAggregationResults, IntermediateBucketResult, IntermediateHistogramBucketEntry,
+        VecWithNames,
+    };
+
+    /// Results with a single histogram bucket with the key `key` and the sub-aggregation `sub`.
+    fn histogram(
+        key: f64,
+        sub_aggregation: IntermediateAggregationResults,
+    ) -> IntermediateAggregationResults {
+        IntermediateAggregationResults {
+            metrics: None,
+            buckets: Some(VecWithNames {
+                values: vec![IntermediateBucketResult::HistogramVec {
+                    column_type: None,
+                    buckets: vec![IntermediateHistogramBucketEntry {
+                        key,
+                        doc_count: 0,
+                        sub_aggregation,
+                    }],
+                }],
+                keys: vec![String::new()],
+            }),
+        }
+    }
+
+    #[test]
+    fn collapses_failures_into_sub_aggregations() {
+        // RON writes every NaN as `NaN`, and loses the payload.
+        let nan = f64::from_bits(0x7ff4_0000_0000_0001);
+        let failing = histogram(nan, Default::default());
+        let value = histogram(1.0, histogram(2.0, failing.clone()));
+        let check = roundtrip::<_, formats::Ron>;
+
+        let (value, reason) = collapse(value, "outer failure".to_string(), check);
+        assert!(cmp_res(&value, &failing, FloatCmp::Bitwise).is_ok());
+        assert!(reason.starts_with("Cmp Mismatch\nbuckets.values[0]"));
+    }
+
+    #[test]
+    fn keeps_values_whose_sub_aggregations_pass() {
+        let value = histogram(1.0, histogram(2.0, Default::default()));
+        let (collapsed, reason) = collapse(
+            value.clone(),
+            "failure".to_string(),
+            roundtrip::<_, formats::Ron>,
+        );
+        assert_eq!(collapsed, value);
+        assert_eq!(reason, "failure");
+    }
+}
@@ -0,0 +1,97 @@
+//! Benchmarks JSON and JSONL files given with `--input`, without writing a struct for them.
+//!
+//! Every file is one scenario of its `serde_json::Value`. With `--infer-schema` there is a second
+//! scenario per file, with the value serialized as structs inferred from it.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::{
+    cli::Cli,
+    schema::{self, Inferred, Schema},
+    test_scenario,
+    test_struct_value::JsonValue,
+    ScenarioResult,
+};
+
+/// A loaded input file, JSONL files are loaded as an array of their lines.
+pub struct Input {
+    pub name: String,
+    pub value: Value,
+}
+
+impl Input {
+    pub fn value_scenario(&self, cli: &Cli) -> ScenarioResult {
+        test_scenario(&self.name, JsonValue(self.value.clone()), cli)
+    }
+
+    pub fn inferred_scenario(&self, cli: &Cli) -> ScenarioResult {
+        let schema = Arc::new(Schema::infer([&self.value]));
+        let inferred = Inferred::new(schema.clone(), schema.conform(&self.value));
+        let name = format!("{} (inferred schema)", self.name);
+        schema::with_schema(schema, || test_scenario(&name, inferred, cli))
+    }
+}
+
+/// Loads the files, directories are searched for `.json` and `.jsonl` files, not recursively.
+pub fn load(paths: &[PathBuf]) -> Result<Vec<Input>> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        for file in files(path)? {
+            inputs.push(Input {
+                name: file.display().to_string(),
+                value: load_file(&file)?,
+            });
+        }
+    }
+    Ok(inputs)
+}
+
+fn files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in
+        std::fs::read_dir(path).with_context(|| format!("could not read {}", path.display()))?
+    {
+        let file = entry?.path();
+        if file.is_file() && (has_extension(&file, "json") || has_extension(&file, "jsonl")) {
+            files.push(file);
+        }
+    }
+    if files.is_empty() {
+        bail!("no .json or .jsonl files in {}", path.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+fn load_file(path: &Path) -> Result<Value> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    if !has_extension(path, "jsonl") {
+        return serde_json::from_str(&content)
+            .with_context(|| format!("could not parse {}", path.display()));
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("could not parse {} line {}", path.display(), i + 1))
+        })
+        .collect::<Result<Vec<Value>>>()
+        .map(Value::Array)
+}
@@ -1,23 +1,78 @@
 use prettytable::cell;
 
-use formats::{Bincode, Bitcode, Ciborium, Deser, Json, Postcard, Rmp, Ron};
+use formats::{Deser, Deserializes, FormatList, FormatVisitor, Formats, Serializes};
 use prettytable::{
-    format::{FormatBuilder, LinePosition, LineSeparator},
+    format::{consts::FORMAT_NO_LINESEP_WITH_TITLE, FormatBuilder, LinePosition, LineSeparator},
     Row, Table,
 };
 use serde::{de::DeserializeOwned, Serialize};
-use test_struct_agg2::IntermediateAggregationResults2;
+use test_struct_agg2::{generate_nested, generate_terms, IntermediateAggregationResults2};
+use test_struct_ddsketch::{Distribution, Sketch};
+use test_struct_floats::float_edge_cases;
+use test_struct_github::load_events;
+use test_struct_value::JsonValue;
 
-use crate::{formats::Speedy, test_struct_agg1::get_test_struct};
+use crate::{
+    alloc_counter::AllocStats,
+    diff::Diff,
+    panic_guard::catch_panic,
+    stats::TimingStats,
+    test_struct_agg1::{generate_histogram, get_test_struct},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use cli::{Cli, FloatCmp, OutputFormat};
+use export::RunMetadata;
+use std::{
+    cell::Cell,
+    fs::File,
+    hint::black_box,
+    io::{BufWriter, Write},
+    time::Instant,
+};
+mod alloc_counter;
+mod any_probe;
+mod baseline;
+mod bson_root;
+mod cli;
+mod compat;
+mod diff;
+mod distributions;
+mod dos;
+mod export;
 mod formats;
+mod fuzz;
+mod input;
+mod nesting;
+mod panic_guard;
+mod readme;
+mod robustness;
+mod schema;
+mod stats;
+mod subprocess;
+mod sweep;
 mod test_struct_agg1;
 mod test_struct_agg2;
+mod test_struct_compat;
+mod test_struct_ddsketch;
+mod test_struct_floats;
+mod test_struct_github;
+mod test_struct_hostile;
+mod test_struct_value;
 
-pub use speedy::LittleEndian as SpeedyLE;
-pub use speedy::Readable as SpeedyR;
-pub use speedy::Writable as SpeedyW;
+#[cfg(feature = "alloc-counter")]
+#[global_allocator]
+static ALLOCATOR: alloc_counter::CountingAllocator = alloc_counter::CountingAllocator;
+
+fn get_table(output: OutputFormat) -> Table {
+    if output == OutputFormat::Plain {
+        let mut table = Table::new();
+        table.set_format(*FORMAT_NO_LINESEP_WITH_TITLE);
+        return table;
+    }
+    get_markdown_table()
+}
 
 fn get_markdown_table() -> Table {
     let mut table = Table::new();
@@ -34,136 +89,768 @@ fn get_markdown_table() -> Table {
     table
 }
 
-fn main() {
-    //let test_struct = get_test_struct();
-    let fs = std::fs::read_to_string("src/test_data_percentiles_1.json").unwrap();
-    let test_struct: IntermediateAggregationResults2 = serde_json::from_str(&fs).unwrap();
-    test_scenario("Percentiles Aggregation", test_struct);
+/// How often each format is run before and while measuring, and how its result is checked.
+#[derive(Debug, Clone, Copy)]
+struct BenchConfig {
+    /// Untimed iterations to warm up caches and the allocator.
+    warmup: usize,
+    /// Timed iterations the statistics are computed from.
+    iterations: usize,
+    /// How floats of the deserialized value are compared with the original.
+    float_cmp: FloatCmp,
+    /// Corrupted inputs of each kind per format, with `--robustness`.
+    corruptions: usize,
+}
+
+/// A named test data set, which is loaded and run through all selected formats.
+struct Scenario {
+    name: &'static str,
+    run: fn(&str, &Cli) -> Result<ScenarioResult>,
+}
+
+/// Buckets of the generated scenarios, as many as in "Aggregation Artificial".
+const GENERATED_BUCKETS: usize = 10_000;
+
+fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: "Percentiles Aggregation",
+            run: |name, cli| {
+                let test_struct: IntermediateAggregationResults2 =
+                    load_json("src/test_data_percentiles_1.json")?;
+                Ok(test_scenario(name, test_struct, cli))
+            },
+        },
+        Scenario {
+            name: "Term Aggregation",
+            run: |name, cli| {
+                let test_struct: IntermediateAggregationResults2 =
+                    load_json("src/test_data_term_agg.json")?;
+                Ok(test_scenario(name, test_struct, cli))
+            },
+        },
+        Scenario {
+            name: "Aggregation Artificial",
+            run: |name, cli| Ok(test_scenario(name, get_test_struct(), cli)),
+        },
+        Scenario {
+            name: "GitHub Events",
+            run: |name, cli| {
+                let events = load_events("src/json_value.json")
+                    .context("could not load src/json_value.json")?;
+                Ok(test_scenario(name, events, cli))
+            },
+        },
+        Scenario {
+            name: "Histogram Generated",
+            run: |name, cli| {
+                let test_struct = generate_histogram(GENERATED_BUCKETS, distributions::SEED);
+                Ok(test_scenario(name, test_struct, cli))
+            },
+        },
+        Scenario {
+            name: "Terms Generated",
+            run: |name, cli| {
+                let test_struct = generate_terms(GENERATED_BUCKETS, distributions::SEED);
+                Ok(test_scenario(name, test_struct, cli))
+            },
+        },
+        Scenario {
+            name: "Nested Aggregations",
+            run: |name, cli| {
+                let test_struct = generate_nested(
+                    cli.depth as usize,
+                    cli.fan_out as usize,
+                    distributions::SEED,
+                );
+                Ok(test_scenario(name, test_struct, cli))
+            },
+        },
+        Scenario {
+            name: "Float Edge Cases",
+            run: |name, cli| {
+                // With `PartialEq` NaN never roundtrips, whatever --float-cmp says the floats have
+                // to keep their bits.
+                let config = BenchConfig {
+                    float_cmp: FloatCmp::Bitwise,
+                    ..cli.bench_config()
+                };
+                Ok(test_scenario_with(name, float_edge_cases(), cli, &config))
+            },
+        },
+        Scenario {
+            name: "DDSketch Uniform",
+            run: |name, cli| {
+                let sketch = Sketch::from_distribution(Distribution::Uniform);
+                Ok(test_scenario(name, sketch, cli))
+            },
+        },
+        Scenario {
+            name: "DDSketch LogNormal",
+            run: |name, cli| {
+                let sketch = Sketch::from_distribution(Distribution::LogNormal);
+                Ok(test_scenario(name, sketch, cli))
+            },
+        },
+        Scenario {
+            name: "DDSketch Pareto",
+            run: |name, cli| {
+                let sketch = Sketch::from_distribution(Distribution::Pareto);
+                Ok(test_scenario(name, sketch, cli))
+            },
+        },
+        Scenario {
+            name: "JSON Value",
+            run: |name, cli| {
+                let value: serde_json::Value = load_json("src/json_value.json")?;
+                Ok(test_scenario(name, JsonValue(value), cli))
+            },
+        },
+    ]
+}
+
+/// Where machine-readable results are written to, stdout if no `--output-file` is given.
+fn open_output(cli: &Cli) -> Result<Box<dyn Write>> {
+    Ok(match &cli.output_file {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).with_context(|| {
+                format!("could not create {}", path.display())
+            })?))
+        }
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+fn load_json<T: DeserializeOwned>(path: &str) -> Result<T> {
+    let fs = std::fs::read_to_string(path).with_context(|| format!("could not read {path}"))?;
+    serde_json::from_str(&fs).with_context(|| format!("could not parse {path}"))
+}
+
+/// Runs one format on a test struct.
+type FormatFn<T> = fn(&T, &BenchConfig) -> FormatResult;
 
-    let fs = std::fs::read_to_string("src/test_data_term_agg.json").unwrap();
-    let test_struct: IntermediateAggregationResults2 = serde_json::from_str(&fs).unwrap();
-    test_scenario("Term Aggregation", test_struct);
+/// All formats, in the order they are run and listed.
+fn formats<T: Formats + PartialEq + Diff + std::fmt::Debug>() -> FormatList<FormatFn<T>> {
+    formats::list(&Bench)
+}
+
+/// Makes the [`FormatFn`] of a format.
+struct Bench;
 
-    let test_struct = get_test_struct();
-    test_scenario("Aggregation Artificial", test_struct);
+impl<T: PartialEq + Diff + std::fmt::Debug> FormatVisitor<T> for Bench {
+    type Output = FormatFn<T>;
+    fn visit<F: Serializes<T> + Deserializes<T>>(&self) -> FormatFn<T> {
+        test_format::<T, F>
+    }
 }
 
-fn test_scenario<
-    T: PartialEq
-        + Serialize
-        + DeserializeOwned
-        + std::fmt::Debug
-        + SpeedyW<SpeedyLE>
-        + SpeedyR<'static, SpeedyLE>,
->(
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    if cli.list {
+        println!("Scenarios:");
+        for scenario in scenarios() {
+            println!("  {}", scenario.name);
+        }
+        println!("Formats:");
+        for name in formats::names() {
+            println!("  {}", name);
+        }
+        return Ok(());
+    }
+
+    if let Some(format) = &cli.nesting_child {
+        return nesting::run_child(&cli, format);
+    }
+    if let (Some(format), Some(attack)) = (&cli.dos_child, &cli.dos_attack) {
+        return dos::run_child(&cli, format, attack);
+    }
+    if cli.probe_nesting {
+        return nesting::probe(&cli);
+    }
+    if cli.fuzz {
+        return fuzz::run(&cli);
+    }
+    if cli.compat {
+        return compat::run(&cli);
+    }
+    if cli.dos {
+        return dos::run(&cli);
+    }
+
+    let inputs = input::load(&cli.inputs)?;
+    let selected: Vec<Scenario> = scenarios()
+        .into_iter()
+        .filter(|scenario| !cli.sweep && inputs.is_empty() && cli.runs_scenario(scenario.name))
+        .collect();
+    if selected.is_empty() && inputs.is_empty() && !cli.sweep {
+        bail!("no scenario matches {:?}, see --list", cli.scenarios);
+    }
+    if !formats::names()
+        .iter()
+        .any(|format| cli.runs_format(format))
+    {
+        bail!("no format matches {:?}, see --list", cli.formats);
+    }
+
+    let metadata = RunMetadata::new(&cli);
+    // Load the baseline up front, so a typo doesn't surface only after the whole run.
+    let baseline = cli.baseline.as_deref().map(baseline::load).transpose()?;
+    if cli.output.is_table() && cli.robustness {
+        println!(
+            "Robustness: up to {} corrupted inputs of each kind per format",
+            cli.corruptions
+        );
+    } else if cli.output.is_table() {
+        println!(
+            "Warmup: {} iterations, measured: {} iterations",
+            cli.warmup, cli.iterations
+        );
+    }
+    let mut results = if cli.sweep {
+        sweep::run(&cli)?
+    } else {
+        Vec::new()
+    };
+    let mut report = |res: ScenarioResult| {
+        if cli.output.is_table() && cli.robustness {
+            robustness::print_table(&res, cli.output);
+        } else if cli.output.is_table() {
+            print_table(&res, cli.output);
+        }
+        results.push(res);
+    };
+    for scenario in selected {
+        report((scenario.run)(scenario.name, &cli)?);
+    }
+    for input in &inputs {
+        report(input.value_scenario(&cli));
+        if cli.infer_schema {
+            report(input.inferred_scenario(&cli));
+        }
+    }
+
+    match cli.output {
+        OutputFormat::Json => export::write_json(open_output(&cli)?, &metadata, &results)?,
+        OutputFormat::Csv => export::write_csv(open_output(&cli)?, &metadata, &results)?,
+        OutputFormat::Markdown | OutputFormat::Plain => {}
+    }
+    if cli.update_readme {
+        readme::update(&metadata, &results)?;
+    }
+    if let Some(name) = &cli.save_baseline {
+        baseline::save(name, &metadata, &results)?;
+    }
+    if let Some(baseline) = baseline {
+        let (table, regressions) =
+            baseline::compare(&baseline, &results, cli.regression_threshold, cli.output);
+        let mut out: Box<dyn Write> = if cli.output.is_table() {
+            Box::new(std::io::stdout().lock())
+        } else {
+            Box::new(std::io::stderr().lock())
+        };
+        writeln!(
+            out,
+            "Comparison with baseline {} from {}",
+            cli.baseline.as_deref().unwrap_or_default(),
+            baseline.metadata.timestamp
+        )?;
+        table.print(&mut out)?;
+        if regressions > 0 {
+            bail!(
+                "{} regressions above {}%",
+                regressions,
+                cli.regression_threshold
+            );
+        }
+    }
+    Ok(())
+}
+
+fn test_scenario<T: Formats + PartialEq + Diff + std::fmt::Debug>(
     name: &str,
     test_struct: T,
-) {
-    println!("Scenario: {}", name);
-    let mut table = get_markdown_table();
+    cli: &Cli,
+) -> ScenarioResult {
+    test_scenario_with(name, test_struct, cli, &cli.bench_config())
+}
+
+/// Like [`test_scenario`], with a different number of iterations than given on the command line.
+fn test_scenario_with<T: Formats + PartialEq + Diff + std::fmt::Debug>(
+    name: &str,
+    test_struct: T,
+    cli: &Cli,
+    config: &BenchConfig,
+) -> ScenarioResult {
+    if cli.output.is_table() {
+        println!("Scenario: {}", name);
+    } else {
+        eprintln!("Scenario: {}", name);
+    }
+    let results: Vec<FormatResult> = if cli.robustness {
+        robustness::test_scenario(name, &test_struct, cli, config)
+    } else {
+        formats::<T>()
+            .into_iter()
+            .filter(|(format, _)| cli.runs_format(format))
+            .map(|(format, test_format)| match test_format {
+                Some(test_format) => test_format(&test_struct, config),
+                None => FormatResult::skipped(format),
+            })
+            .collect()
+    };
+    ScenarioResult {
+        name: name.to_string(),
+        results,
+    }
+}
+
+/// The results of all formats on one scenario.
+#[derive(Debug)]
+struct ScenarioResult {
+    name: String,
+    results: Vec<FormatResult>,
+}
+
+impl ScenarioResult {
+    /// The format with the lowest median roundtrip time.
+    fn fastest(&self) -> Option<&FormatResult> {
+        self.results
+            .iter()
+            .filter(|res| res.is_ok())
+            .min_by_key(|res| res.roundtrip_time.median)
+    }
+}
+
+fn print_table(scenario: &ScenarioResult, output: OutputFormat) {
+    scenario_table(scenario, output).printstd();
+    for res in &scenario.results {
+        if !res.mismatches.is_empty() {
+            println!("{} mismatches:", res.format);
+            for mismatch in &res.mismatches {
+                println!("  {mismatch}");
+            }
+        }
+    }
+}
+
+fn scenario_table(scenario: &ScenarioResult, output: OutputFormat) -> Table {
+    let mut table = get_table(output);
 
     let mut row = Row::empty();
     row.add_cell(cell!["Format"]);
     row.add_cell(cell!["Result"]);
     row.add_cell(cell!["Serialized Size"]);
-    row.add_cell(cell!["Serialize Time [ns]"]);
-    row.add_cell(cell!["Deserialize Time [ns]"]);
-    row.add_cell(cell!["Roundtrip Time [ns]"]);
-
+    for phase in ["Serialize", "Deserialize"] {
+        row.add_cell(cell![format!("{phase} Min [ns]")]);
+        row.add_cell(cell![format!("{phase} Inlier Median [ns]")]);
+        row.add_cell(cell![format!("{phase} Mean [ns]")]);
+        row.add_cell(cell![format!("{phase} P99 [ns]")]);
+    }
+    for phase in ["Serialize", "Deserialize"] {
+        row.add_cell(cell![format!("{phase} [MB/s]")]);
+        row.add_cell(cell![format!("{phase} [objects/s]")]);
+    }
+    if alloc_counter::ENABLED {
+        for phase in ["Serialize", "Deserialize"] {
+            row.add_cell(cell![format!("{phase} Allocs")]);
+            row.add_cell(cell![format!("{phase} Alloc [bytes]")]);
+            row.add_cell(cell![format!("{phase} Peak [bytes]")]);
+        }
+    }
+    row.add_cell(cell!["Roundtrip Inlier Median [ns]"]);
+    row.add_cell(cell!["Roundtrip 95% CI [ns]"]);
+    row.add_cell(cell!["Outliers"]);
+    row.add_cell(cell!["vs Fastest"]);
+    row.add_cell(cell!["CI Overlaps"]);
     table.set_titles(row);
-    table.add_row(get_row_for_format::<_, Json>(&test_struct));
-    table.add_row(get_row_for_format::<_, Ron>(&test_struct));
-    table.add_row(get_row_for_format::<_, Bincode>(&test_struct));
-    //table.add_row(get_row_for_format::<_, Bson>(&test_struct));
-    table.add_row(get_row_for_format::<_, Bitcode>(&test_struct));
-    table.add_row(get_row_for_format::<_, Rmp>(&test_struct));
-    table.add_row(get_row_for_format::<_, Postcard>(&test_struct));
-    table.add_row(get_row_for_format::<_, Ciborium>(&test_struct));
-    table.add_row(get_row_for_format::<_, Speedy>(&test_struct));
-    table.printstd();
-}
-
-#[derive(Debug, Default)]
+
+    for res in &scenario.results {
+        table.add_row(get_row_for_format(res, scenario));
+    }
+    table
+}
+
+#[derive(Debug, Default, Serialize)]
 struct FormatResult {
-    serialize_time: u128,
-    deserialize_time: u128,
+    format: String,
+    /// Serialize time of every measured iteration in ns.
+    serialize_samples: Vec<u128>,
+    /// Deserialize time of every measured iteration in ns.
+    deserialize_samples: Vec<u128>,
+    serialize_time: TimingStats,
+    deserialize_time: TimingStats,
     serialized_size: usize,
-    roundtrip_time: u128,
+    roundtrip_time: TimingStats,
+    /// Allocations of one serialize call, if the `alloc-counter` feature is enabled.
+    serialize_allocs: Option<AllocStats>,
+    /// Allocations of one deserialize call, if the `alloc-counter` feature is enabled.
+    deserialize_allocs: Option<AllocStats>,
     result: String,
+    /// Where the deserialized value differs from the original, on "Cmp Mismatch".
+    mismatches: Vec<String>,
+    /// What the format made of corrupted inputs, with `--robustness`.
+    corruption: Option<robustness::CorruptionStats>,
 }
 
-fn get_row_for_format<
-    T: PartialEq
-        + Serialize
-        + DeserializeOwned
-        + std::fmt::Debug
-        + SpeedyW<SpeedyLE>
-        + SpeedyR<'static, SpeedyLE>,
-    F: Deser,
->(
-    test_struct: &T,
-) -> Row {
-    let res = test_format::<T, F>(test_struct);
+/// Result of a format that can't handle the type of the scenario.
+const SKIPPED: &str = "n/a";
+
+impl FormatResult {
+    fn skipped(format: String) -> FormatResult {
+        FormatResult {
+            format,
+            result: SKIPPED.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.result == "Ok"
+    }
+
+    fn is_skipped(&self) -> bool {
+        self.result == SKIPPED
+    }
+
+    /// Whether the format got through the measured iterations and has timings, also on a
+    /// "Cmp Mismatch".
+    fn is_measured(&self) -> bool {
+        !self.serialize_samples.is_empty()
+    }
 
+    /// Serialize plus deserialize time of each measured iteration.
+    fn roundtrip_samples(&self) -> Vec<u128> {
+        self.serialize_samples
+            .iter()
+            .zip(&self.deserialize_samples)
+            .map(|(ser, deser)| ser + deser)
+            .collect()
+    }
+}
+
+fn get_row_for_format(res: &FormatResult, scenario: &ScenarioResult) -> Row {
     let mut row = Row::empty();
-    row.add_cell(cell!(F::name()));
-    row.add_cell(cell!(res.result));
+    row.add_cell(cell!(res.format));
+    row.add_cell(cell!(first_line(&res.result)));
+    if res.is_skipped() {
+        return row;
+    }
+    // A format that failed has no timings, and a size only if serializing worked.
+    if !res.is_measured() {
+        if res.serialized_size > 0 {
+            row.add_cell(cell!(res.serialized_size));
+        }
+        return row;
+    }
     row.add_cell(cell!(res.serialized_size));
-    row.add_cell(cell!(res.serialize_time));
-    row.add_cell(cell!(res.deserialize_time));
-    row.add_cell(cell!(res.roundtrip_time));
+    for stats in [res.serialize_time, res.deserialize_time] {
+        row.add_cell(cell!(stats.min));
+        row.add_cell(cell!(stats.median));
+        row.add_cell(cell!(stats.mean));
+        row.add_cell(cell!(stats.p99));
+    }
+    for stats in [res.serialize_time, res.deserialize_time] {
+        row.add_cell(cell!(format!(
+            "{:.1}",
+            stats.megabytes_per_sec(res.serialized_size)
+        )));
+        row.add_cell(cell!(format!("{:.0}", stats.ops_per_sec())));
+    }
+    if alloc_counter::ENABLED {
+        for allocs in [res.serialize_allocs, res.deserialize_allocs] {
+            let allocs = allocs.unwrap_or_default();
+            row.add_cell(cell!(allocs.allocations));
+            row.add_cell(cell!(allocs.allocated_bytes));
+            row.add_cell(cell!(allocs.peak_live_bytes));
+        }
+    }
+    let roundtrip = res.roundtrip_time;
+    row.add_cell(cell!(roundtrip.median));
+    row.add_cell(cell!(format!(
+        "{} - {}",
+        roundtrip.ci_low, roundtrip.ci_high
+    )));
+    row.add_cell(cell!(roundtrip.outliers));
+    row.add_cell(cell!(compare_to_fastest(res, scenario.fastest())));
+    row.add_cell(cell!(overlapping_formats(res, scenario)));
     row
 }
 
-fn test_format<
-    T: PartialEq
-        + Serialize
-        + DeserializeOwned
-        + std::fmt::Debug
-        + SpeedyW<SpeedyLE>
-        + SpeedyR<'static, SpeedyLE>,
-    F: Deser,
->(
+/// Describes how the roundtrip time compares to the fastest format of the scenario.
+fn compare_to_fastest(res: &FormatResult, fastest: Option<&FormatResult>) -> String {
+    let Some(fastest) = fastest.filter(|_| res.is_ok()) else {
+        return String::new();
+    };
+    if fastest.format == res.format {
+        "fastest".to_string()
+    } else if res.roundtrip_time.overlaps(&fastest.roundtrip_time) {
+        "not significant".to_string()
+    } else {
+        let slower = res.roundtrip_time.median as f64 / fastest.roundtrip_time.median as f64;
+        format!("+{:.1}%", (slower - 1.0) * 100.0)
+    }
+}
+
+/// The other formats whose roundtrip time isn't significantly different, i.e. whose confidence
+/// intervals overlap.
+fn overlapping_formats(res: &FormatResult, scenario: &ScenarioResult) -> String {
+    if !res.is_ok() {
+        return String::new();
+    }
+    scenario
+        .results
+        .iter()
+        .filter(|other| other.is_ok() && other.format != res.format)
+        .filter(|other| res.roundtrip_time.overlaps(&other.roundtrip_time))
+        .map(|other| other.format.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn test_format<T: PartialEq + Diff, F: Serializes<T> + Deserializes<T>>(
     test_struct: &T,
+    config: &BenchConfig,
 ) -> FormatResult {
-    let start = std::time::Instant::now();
-    let output = F::serialize(test_struct);
-    if output.is_err() {
-        return FormatResult {
-            result: format!("Ser Err: {:?}", output.unwrap_err()),
-            ..Default::default()
-        };
-    }
+    let (serialized_size, output) = match catch_panic(|| F::serialize(test_struct)) {
+        Ok(Ok(output)) => output,
+        Ok(Err(err)) => {
+            return FormatResult {
+                format: F::name(),
+                result: error_result::<F>("Ser Err", &err),
+                ..Default::default()
+            }
+        }
+        Err(panic) => {
+            return FormatResult {
+                format: F::name(),
+                result: format!("Ser Panic: {panic}"),
+                ..Default::default()
+            }
+        }
+    };
+    let deser: T = match catch_panic(|| F::deserialize(output.clone())) {
+        Ok(Ok(deser)) => deser,
+        Ok(Err(err)) => {
+            return FormatResult {
+                format: F::name(),
+                serialized_size,
+                result: error_result::<F>("Deser Err", &err),
+                ..Default::default()
+            }
+        }
+        Err(panic) => {
+            return FormatResult {
+                format: F::name(),
+                serialized_size,
+                result: format!("Deser Panic: {panic}"),
+                ..Default::default()
+            }
+        }
+    };
+    let (result, mismatches) = match cmp_res(test_struct, &deser, config.float_cmp) {
+        Ok(()) => ("Ok", Vec::new()),
+        Err(mismatches) => ("Cmp Mismatch", mismatches),
+    };
 
-    let serialize_time = std::time::Instant::now() - start;
-    let start = std::time::Instant::now();
-    let (serialized_size, output) = output.unwrap();
-    let deser: Result<T> = F::deserialize(output);
+    // The first roundtrip above already succeeded, but a format can still fail or panic on a
+    // later call. `phase` tells which call panicked.
+    let phase = Cell::new("Ser Panic");
+    let measured = catch_panic(|| {
+        // Allocations are counted on a separate untimed run. The allocator only counts inside
+        // `measure`, the timed calls below just pay for checking a flag.
+        let (serialized, serialize_allocs) = alloc_counter::measure(|| F::serialize(test_struct));
+        drop(serialized.map_err(|err| error_result::<F>("Ser Err", &err))?);
+        phase.set("Deser Panic");
+        let input = output.clone();
+        let (deser, deserialize_allocs) =
+            alloc_counter::measure(|| <F as Deserializes<T>>::deserialize(input));
+        drop(deser.map_err(|err| error_result::<F>("Deser Err", &err))?);
 
-    if deser.is_err() {
-        return FormatResult {
-            serialize_time: serialize_time.as_nanos(),
-            serialized_size,
-            result: format!("Deser Err: {:?}", deser.unwrap_err()),
-            ..Default::default()
-        };
-    }
+        for _ in 0..config.warmup {
+            phase.set("Ser Panic");
+            let (_, output) = F::serialize(black_box(test_struct))
+                .map_err(|err| error_result::<F>("Ser Err", &err))?;
+            phase.set("Deser Panic");
+            let _: T = F::deserialize(black_box(output))
+                .map_err(|err| error_result::<F>("Deser Err", &err))?;
+        }
 
-    let deserialize_time = std::time::Instant::now() - start;
-    let cmp = cmp_res(test_struct, &deser.unwrap());
-    let result = if cmp.is_err() { "Cmp Mismatch" } else { "Ok" };
-    FormatResult {
-        serialize_time: serialize_time.as_nanos(),
-        deserialize_time: deserialize_time.as_nanos(),
-        roundtrip_time: deserialize_time.as_nanos() + serialize_time.as_nanos(),
+        // Errors are checked after taking the time, a failed call ends the measurement like in
+        // the warmup.
+        let mut serialize_samples = Vec::with_capacity(config.iterations);
+        let mut deserialize_samples = Vec::with_capacity(config.iterations);
+        for _ in 0..config.iterations {
+            phase.set("Ser Panic");
+            let start = Instant::now();
+            let serialized = F::serialize(black_box(test_struct));
+            serialize_samples.push(start.elapsed().as_nanos());
+            drop(black_box(
+                serialized.map_err(|err| error_result::<F>("Ser Err", &err))?,
+            ));
+
+            phase.set("Deser Panic");
+            let input = output.clone();
+            let start = Instant::now();
+            let deser: Result<T> = F::deserialize(black_box(input));
+            deserialize_samples.push(start.elapsed().as_nanos());
+            drop(black_box(
+                deser.map_err(|err| error_result::<F>("Deser Err", &err))?,
+            ));
+        }
+        Ok((
+            serialize_allocs,
+            deserialize_allocs,
+            serialize_samples,
+            deserialize_samples,
+        ))
+    });
+    let (serialize_allocs, deserialize_allocs, serialize_samples, deserialize_samples) =
+        match measured {
+            Ok(Ok(measured)) => measured,
+            Ok(Err(result)) => {
+                return FormatResult {
+                    format: F::name(),
+                    serialized_size,
+                    result,
+                    ..Default::default()
+                }
+            }
+            Err(panic) => {
+                return FormatResult {
+                    format: F::name(),
+                    serialized_size,
+                    result: format!("{}: {panic}", phase.get()),
+                    ..Default::default()
+                }
+            }
+        };
+    let mut res = FormatResult {
+        format: F::name(),
+        serialize_samples,
+        deserialize_samples,
         serialized_size,
+        serialize_allocs,
+        deserialize_allocs,
         result: result.to_string(),
+        mismatches,
+        ..Default::default()
+    };
+    res.serialize_time = TimingStats::from_samples(&res.serialize_samples);
+    res.deserialize_time = TimingStats::from_samples(&res.deserialize_samples);
+    res.roundtrip_time = TimingStats::from_samples(&res.roundtrip_samples());
+    res
+}
+
+/// Result text of a failed roundtrip, types the format can't handle are not reported as errors.
+fn error_result<F: Deser>(prefix: &str, err: &anyhow::Error) -> String {
+    if F::is_unsupported(err) {
+        format!("Unsupported: {}", err)
+    } else {
+        format!("{}: {:?}", prefix, err)
     }
 }
 
-fn cmp_res<T: PartialEq>(orig: &T, deser: &T) -> anyhow::Result<()> {
-    if orig != deser {
-        anyhow::bail!("Equality Missmatch");
+/// The first line of a result. Errors are reported with their whole chain, which doesn't fit into
+/// a table cell or a one-line summary.
+fn first_line(result: &str) -> &str {
+    result.lines().next().unwrap_or_default()
+}
+
+/// Compares the deserialized value with the original, on a mismatch with the paths that differ.
+fn cmp_res<T: PartialEq + Diff>(
+    orig: &T,
+    deser: &T,
+    float_cmp: FloatCmp,
+) -> Result<(), Vec<String>> {
+    let mismatches = match float_cmp {
+        FloatCmp::PartialEq if orig == deser => return Ok(()),
+        FloatCmp::PartialEq => {
+            let mut mismatches = orig.diff(deser, float_cmp);
+            if mismatches.is_empty() {
+                // `PartialEq` impls that compare more than is serialized, or NaN in a custom impl.
+                mismatches.push("<root>: not equal, but serialized the same".to_string());
+            }
+            mismatches
+        }
+        FloatCmp::Bitwise => orig.diff(deser, float_cmp),
+    };
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Calls of [`Flaky`], it fails from the third deserialize call on, in the timed loop.
+    static DESERIALIZE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Flaky;
+
+    impl Deser for Flaky {
+        type Serialized = Vec<u8>;
+        fn name() -> String {
+            "Flaky".to_string()
+        }
+    }
+
+    impl Serializes<u32> for Flaky {
+        fn serialize(t: &u32) -> Result<(usize, Vec<u8>)> {
+            Ok((4, t.to_le_bytes().to_vec()))
+        }
+    }
+
+    impl Deserializes<u32> for Flaky {
+        fn deserialize(s: Vec<u8>) -> Result<u32> {
+            if DESERIALIZE_CALLS.fetch_add(1, Ordering::Relaxed) >= 2 {
+                anyhow::bail!("fails after two calls");
+            }
+            Ok(u32::from_le_bytes(s.try_into().unwrap()))
+        }
+    }
+
+    fn config() -> BenchConfig {
+        BenchConfig {
+            warmup: 0,
+            iterations: 10,
+            float_cmp: FloatCmp::PartialEq,
+            corruptions: 0,
+        }
+    }
+
+    #[test]
+    fn errors_in_the_timed_loop_fail_the_format() {
+        let res = test_format::<u32, Flaky>(&7, &config());
+        assert!(res.result.starts_with("Deser Err: fails after two calls"));
+        assert!(!res.is_measured());
+        assert_eq!(res.serialized_size, 4);
+
+        let scenario = ScenarioResult {
+            name: "Flaky".to_string(),
+            results: Vec::new(),
+        };
+        let row = get_row_for_format(&res, &scenario);
+        let cells: Vec<String> = row.iter().map(|cell| cell.get_content()).collect();
+        assert_eq!(cells, ["Flaky", "Deser Err: fails after two calls", "4"]);
+    }
+
+    #[test]
+    fn measured_formats_have_all_columns() {
+        let res = test_format::<u32, formats::Bincode>(&7, &config());
+        assert_eq!(res.result, "Ok");
+        assert_eq!(res.serialize_samples.len(), 10);
+        let scenario = ScenarioResult {
+            name: "Bincode".to_string(),
+            results: Vec::new(),
+        };
+        let row = get_row_for_format(&res, &scenario);
+        assert!(row.len() > 3);
+        assert_eq!(row.get_cell(2).unwrap().get_content(), "4");
     }
-    Ok(())
 }
@@ -0,0 +1,146 @@
+//! Probes how deeply nested aggregations can get before a format fails.
+//!
+//! A stack overflow aborts the process, so every depth is tried in a child process running one
+//! format on a chain of aggregations (fan-out 1). The depths are doubled until the first failure,
+//! then the exact limit is found by bisection.
+
+use anyhow::{bail, Context, Result};
+use prettytable::{cell, Row};
+
+use crate::{
+    cli::Cli, distributions::SEED, first_line, formats, get_table, subprocess,
+    test_struct_agg2::generate_nested, BenchConfig, FormatResult, IntermediateAggregationResults2,
+    SKIPPED,
+};
+
+/// Printed by the child once the tree is built, cloned, compared and dropped.
+const HARNESS_OK: &str = "harness ok";
+/// Prefix of the line with the result of the format.
+const RESULT_PREFIX: &str = "result: ";
+
+enum Outcome {
+    Ok,
+    Failed(String),
+    /// Our own recursive `Clone`, `PartialEq` or `Drop` overflowed, not the format.
+    HarnessLimit,
+}
+
+/// Entry point of the child process, runs `format` on a chain of `cli.depth` aggregations.
+pub fn run_child(cli: &Cli, format: &str) -> Result<()> {
+    let tree = generate_nested(cli.depth as usize, 1, SEED);
+    assert!(tree.clone() == tree);
+    println!("{HARNESS_OK}");
+
+    let Some((name, test_format)) = formats::<IntermediateAggregationResults2>()
+        .into_iter()
+        .find(|(name, _)| name == format)
+    else {
+        bail!("unknown format {format}");
+    };
+    let config = BenchConfig {
+        warmup: 0,
+        iterations: 1,
+        float_cmp: cli.float_cmp,
+        corruptions: cli.corruptions,
+    };
+    let res = match test_format {
+        Some(test_format) => test_format(&tree, &config),
+        None => FormatResult::skipped(name),
+    };
+    println!("{RESULT_PREFIX}{}", res.result);
+    Ok(())
+}
+
+fn run_depth(format: &str, depth: usize) -> Result<Outcome> {
+    let output = subprocess::run_self(&[
+        "--nesting-child".to_string(),
+        format.to_string(),
+        "--depth".to_string(),
+        depth.to_string(),
+    ])?;
+    if !output.stdout.lines().any(|line| line == HARNESS_OK) {
+        return Ok(Outcome::HarnessLimit);
+    }
+    if let Some(crash) = output.crash() {
+        return Ok(Outcome::Failed(crash));
+    }
+    let result = output
+        .stdout
+        .lines()
+        .find_map(|line| line.strip_prefix(RESULT_PREFIX))
+        .with_context(|| format!("no result from the child for {format} at depth {depth}"))?;
+    Ok(if result == "Ok" {
+        Outcome::Ok
+    } else {
+        Outcome::Failed(first_line(result).to_string())
+    })
+}
+
+/// The deepest depth that worked and the first one that didn't, with its outcome.
+fn find_limit(format: &str, max_depth: usize) -> Result<(usize, Option<(usize, Outcome)>)> {
+    let mut ok = 0;
+    let mut depth = 1;
+    let (mut failing, mut outcome) = loop {
+        match run_depth(format, depth)? {
+            Outcome::Ok if depth >= max_depth => return Ok((depth, None)),
+            Outcome::Ok => {
+                ok = depth;
+                depth = (depth * 2).min(max_depth);
+            }
+            outcome => break (depth, outcome),
+        }
+    };
+    while failing - ok > 1 {
+        let mid = ok + (failing - ok) / 2;
+        match run_depth(format, mid)? {
+            Outcome::Ok => ok = mid,
+            mid_outcome => (failing, outcome) = (mid, mid_outcome),
+        }
+    }
+    Ok((ok, Some((failing, outcome))))
+}
+
+/// Finds the nesting limit of every selected format and prints it.
+pub fn probe(cli: &Cli) -> Result<()> {
+    let mut table = get_table(cli.output);
+    let mut row = Row::empty();
+    row.add_cell(cell!["Format"]);
+    row.add_cell(cell!["Deepest Ok"]);
+    row.add_cell(cell!["First Failing Depth"]);
+    row.add_cell(cell!["Failure"]);
+    table.set_titles(row);
+
+    let selected: Vec<_> = formats::<IntermediateAggregationResults2>()
+        .into_iter()
+        .filter(|(format, _)| cli.runs_format(format))
+        .collect();
+    if selected.is_empty() {
+        bail!("no format matches {:?}, see --list", cli.formats);
+    }
+    for (format, test_format) in selected {
+        if test_format.is_none() {
+            table.add_row(Row::new(vec![cell!(format), cell!(SKIPPED)]));
+            continue;
+        }
+        eprintln!("Probing {format}");
+        let (ok, failure) = find_limit(&format, cli.max_depth as usize)?;
+        let (failing, failure) = match failure {
+            None => (String::new(), format!("none up to {}", cli.max_depth)),
+            Some((depth, Outcome::Failed(failure))) => (depth.to_string(), failure),
+            Some((depth, Outcome::HarnessLimit)) => (
+                depth.to_string(),
+                "inconclusive, the test harness overflowed first".to_string(),
+            ),
+            Some((_, Outcome::Ok)) => unreachable!("only failures are returned"),
+        };
+        let mut row = Row::empty();
+        row.add_cell(cell!(format));
+        row.add_cell(cell!(ok));
+        row.add_cell(cell!(failing));
+        row.add_cell(cell!(failure));
+        table.add_row(row);
+    }
+    println!("Nesting limits, one aggregation per level:");
+    table.printstd();
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Turns panics of a format into results, so one broken format doesn't end the whole run.
+
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::Once,
+};
+
+thread_local! {
+    /// Set while the thread runs [`catch_panic`], whose panics end up in the results.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` and returns the message if it panics. The panic hook is silent for panics caught
+/// here, the message ends up in the results.
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    silence_caught_panics();
+    let was_catching = CATCHING.replace(true);
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.set(was_catching);
+    result.map_err(|payload| {
+        payload
+            .downcast_ref::<&str>()
+            .map(|msg| msg.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string())
+    })
+}
+
+/// Installs a hook that skips panics in [`catch_panic`] and passes all others to the previous
+/// one. Done once for the process, swapping hooks per call races with other threads.
+fn silence_caught_panics() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.get() {
+                hook(info);
+            }
+        }));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_become_errors() {
+        assert_eq!(catch_panic(|| 7), Ok(7));
+        assert_eq!(
+            catch_panic(|| panic!("static")),
+            Err::<(), _>("static".to_string())
+        );
+        let len = 3;
+        assert_eq!(
+            catch_panic(|| panic!("formatted {len}")),
+            Err::<(), _>("formatted 3".to_string())
+        );
+        assert_eq!(
+            catch_panic(|| panic::panic_any(7)),
+            Err::<(), _>("unknown panic".to_string())
+        );
+    }
+
+    #[test]
+    fn panics_outside_are_reported_again() {
+        let inner = catch_panic(|| {
+            let nested = catch_panic(|| panic!("nested"));
+            // Still inside the outer call, its panics are silenced too.
+            assert!(CATCHING.get());
+            nested
+        });
+        assert_eq!(inner, Ok(Err("nested".to_string())));
+        assert!(!CATCHING.get());
+
+        let _ = catch_panic(|| panic!("caught"));
+        assert!(!CATCHING.get());
+    }
+}
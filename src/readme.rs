@@ -0,0 +1,177 @@
+//! Rewrites the results sections of the README with the tables of the current run.
+//!
+//! Each scenario has its own section between `<!-- results:NAME -->` and
+//! `<!-- /results:NAME -->`. Scenarios without a section get one appended at the end. The header
+//! between `<!-- results-header -->` and `<!-- /results-header -->` records where the numbers
+//! come from. The serde feature matrix is in `<!-- results:Serde Features -->`.
+
+use anyhow::{Context, Result};
+
+use crate::{cli::OutputFormat, compat, export::RunMetadata, scenario_table, ScenarioResult};
+
+const README_PATH: &str = "README.md";
+
+pub fn update(metadata: &RunMetadata, scenarios: &[ScenarioResult]) -> Result<()> {
+    let mut readme = std::fs::read_to_string(README_PATH)
+        .with_context(|| format!("could not read {README_PATH}"))?;
+
+    readme = replace_section(&readme, "results-header", &header(metadata));
+    readme = replace_section(&readme, "results:Serde Features", &compat::readme_section());
+    for scenario in scenarios {
+        let section = format!("results:{}", scenario.name);
+        let table = scenario_table(scenario, OutputFormat::Markdown).to_string();
+        if readme.contains(&start_marker(&section)) {
+            readme = replace_section(&readme, &section, &table);
+        } else {
+            readme.push_str(&format!(
+                "\n## {}\n\n{}\n\n{}\n{}\n",
+                scenario.name,
+                start_marker(&section),
+                table,
+                end_marker(&section),
+            ));
+        }
+    }
+
+    std::fs::write(README_PATH, readme)
+        .with_context(|| format!("could not write {README_PATH}"))?;
+    eprintln!("Updated {README_PATH}");
+    Ok(())
+}
+
+fn start_marker(name: &str) -> String {
+    format!("<!-- {name} -->")
+}
+
+fn end_marker(name: &str) -> String {
+    format!("<!-- /{name} -->")
+}
+
+/// Replaces the content between the markers of section `name`, if it exists.
+fn replace_section(readme: &str, name: &str, content: &str) -> String {
+    let (start, end) = (start_marker(name), end_marker(name));
+    let Some(start_pos) = readme.find(&start) else {
+        return readme.to_string();
+    };
+    let content_start = start_pos + start.len();
+    let Some(end_pos) = readme[content_start..].find(&end) else {
+        return readme.to_string();
+    };
+    // Blank lines around the content, so markdown doesn't merge it with the markers.
+    format!(
+        "{}\n\n{}\n{}",
+        &readme[..content_start],
+        content,
+        &readme[content_start + end_pos..]
+    )
+}
+
+fn header(metadata: &RunMetadata) -> String {
+    format!(
+        "Generated with `cargo run --release -- --update-readme` on {}.\n\n\
+         - Toolchain: {}\n\
+         - Machine: {}, {}, {} {}\n\
+         - Warmup: {} iterations, measured: {} iterations, allocation counting: {}\n",
+        date(metadata.timestamp),
+        env!("RUSTC_VERSION"),
+        cpu_model(),
+        threads(std::thread::available_parallelism().map_or(1, |threads| threads.get())),
+        metadata.os,
+        metadata.arch,
+        metadata.warmup,
+        metadata.iterations,
+        if metadata.alloc_counter { "on" } else { "off" },
+    )
+}
+
+fn threads(count: usize) -> String {
+    if count == 1 {
+        "1 thread".to_string()
+    } else {
+        format!("{count} threads")
+    }
+}
+
+fn cpu_model() -> String {
+    std::fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|cpuinfo| {
+            cpuinfo
+                .lines()
+                .find(|line| line.starts_with("model name"))
+                .and_then(|line| line.split(':').nth(1))
+                .map(|model| model.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown CPU".to_string())
+}
+
+/// Formats seconds since the unix epoch as `YYYY-MM-DD` (UTC).
+fn date(timestamp: u64) -> String {
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_content_between_the_markers() {
+        let readme = "a\n<!-- x -->\nold\n<!-- /x -->\nb";
+        assert_eq!(
+            replace_section(readme, "x", "new"),
+            "a\n<!-- x -->\n\nnew\n<!-- /x -->\nb"
+        );
+    }
+
+    #[test]
+    fn leaves_the_readme_alone_without_both_markers() {
+        for readme in [
+            "a\nb",
+            "a\n<!-- x -->\nold",
+            "a\nold\n<!-- /x -->",
+            "<!-- /x -->\nold\n<!-- x -->",
+            "<!-- y -->\nold\n<!-- /y -->",
+        ] {
+            assert_eq!(replace_section(readme, "x", "new"), readme);
+        }
+    }
+
+    #[test]
+    fn only_replaces_the_first_of_duplicated_sections() {
+        let readme = "<!-- x -->\none\n<!-- /x -->\n<!-- x -->\ntwo\n<!-- /x -->";
+        assert_eq!(
+            replace_section(readme, "x", "new"),
+            "<!-- x -->\n\nnew\n<!-- /x -->\n<!-- x -->\ntwo\n<!-- /x -->"
+        );
+    }
+
+    #[test]
+    fn date_across_leap_years() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(951_782_400 + 86_400), "2000-03-01");
+        assert_eq!(date(1_709_164_800), "2024-02-29");
+        assert_eq!(date(1_677_628_800 - 86_400), "2023-02-28");
+        assert_eq!(date(1_677_628_800), "2023-03-01");
+        // 2100 is divisible by 100 but not by 400, so no leap year.
+        assert_eq!(date(4_107_542_400 - 86_400), "2100-02-28");
+        assert_eq!(date(4_107_542_400), "2100-03-01");
+        // Any time of the day is the same date.
+        assert_eq!(date(1_709_164_800 + 86_399), "2024-02-29");
+    }
+}
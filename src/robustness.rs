@@ -0,0 +1,563 @@
+//! Feeds every format corrupted versions of its own output: truncated, with single bits flipped
+//! and with random bytes overwritten. Each attempt is classified by what the decoder makes of it,
+//! to see which formats are safe to read from untrusted peers.
+//!
+//! The corrupted inputs are decoded in a child process, as a decoder can abort the process, e.g.
+//! on a failed allocation for a corrupted length, or hang. The child rebuilds the scenario from
+//! the same arguments and reports each outcome. After it died or was killed for hanging, a new
+//! one carries on with the next input.
+
+use std::{fmt, sync::mpsc::RecvTimeoutError, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use prettytable::{cell, Row};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{
+    cli::{Cli, OutputFormat},
+    cmp_res,
+    diff::Diff,
+    distributions, error_result, first_line,
+    formats::{self, Deserializes, FormatVisitor, Formats, RawBytes, Serializes},
+    get_table,
+    panic_guard::catch_panic,
+    subprocess::{self, RunningChild},
+    BenchConfig, FormatResult, ScenarioResult,
+};
+
+/// A decoder that takes longer than this on a corrupted input counts as hanging.
+const HANG_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Prefixes of the lines the child reports on.
+const CASES_PREFIX: &str = "cases: ";
+const DECODING_PREFIX: &str = "decoding: ";
+const OUTCOME_PREFIX: &str = "outcome: ";
+
+/// What the decoders of one format made of the corrupted inputs.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CorruptionStats {
+    pub cases: usize,
+    /// Rejected with an error, the expected outcome.
+    pub errors: usize,
+    /// Read back as the original value, e.g. after flipping a bit that isn't used.
+    pub unchanged: usize,
+    /// Read back as a different value without an error.
+    pub wrong_values: usize,
+    pub panics: usize,
+    /// Took down the process, e.g. with a stack overflow.
+    pub aborts: usize,
+    /// Aborted the process on an allocation the input asked for.
+    pub out_of_memory: usize,
+    /// Still decoding after [`HANG_TIMEOUT`], the process is killed then.
+    pub timeouts: usize,
+    /// The first input of each kind of bad outcome.
+    pub examples: Vec<String>,
+}
+
+impl CorruptionStats {
+    /// The worst outcome, "Ok" if every input was rejected or read back unchanged.
+    fn result(&self) -> &'static str {
+        if self.aborts > 0 {
+            "Abort"
+        } else if self.out_of_memory > 0 {
+            "OOM"
+        } else if self.timeouts > 0 {
+            "Timeout"
+        } else if self.panics > 0 {
+            "Panic"
+        } else if self.wrong_values > 0 {
+            "Wrong Value"
+        } else {
+            "Ok"
+        }
+    }
+
+    fn add(&mut self, mutation: &str, outcome: Outcome) {
+        self.cases += 1;
+        let (count, example) = match outcome {
+            Outcome::Error => (&mut self.errors, None),
+            Outcome::Unchanged => (&mut self.unchanged, None),
+            Outcome::WrongValue => (
+                &mut self.wrong_values,
+                Some(format!("Wrong Value: {mutation}")),
+            ),
+            Outcome::Panic(panic) => (
+                &mut self.panics,
+                Some(format!("Panic: {mutation}: {panic}")),
+            ),
+            Outcome::Abort(crash) => (
+                &mut self.aborts,
+                Some(format!("Abort: {mutation}: {crash}")),
+            ),
+            Outcome::OutOfMemory(bytes) => (
+                &mut self.out_of_memory,
+                Some(format!(
+                    "OOM: {mutation}: allocation of {bytes} bytes failed"
+                )),
+            ),
+            Outcome::Timeout => (&mut self.timeouts, Some(format!("Timeout: {mutation}"))),
+        };
+        *count += 1;
+        if *count == 1 {
+            self.examples.extend(example);
+        }
+    }
+}
+
+/// How a corrupted input is made from the serialized bytes.
+enum Mutation {
+    Truncate(usize),
+    FlipBit(usize),
+    Overwrite(usize, Vec<u8>),
+}
+
+impl Mutation {
+    fn apply(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        match self {
+            Mutation::Truncate(len) => bytes.truncate(*len),
+            Mutation::FlipBit(bit) => bytes[bit / 8] ^= 1 << (bit % 8),
+            Mutation::Overwrite(pos, new) => bytes[*pos..*pos + new.len()].copy_from_slice(new),
+        }
+        bytes
+    }
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mutation::Truncate(len) => write!(f, "truncated to {len} bytes"),
+            Mutation::FlipBit(bit) => write!(f, "bit {} of byte {} flipped", bit % 8, bit / 8),
+            Mutation::Overwrite(pos, new) => {
+                write!(
+                    f,
+                    "bytes {}..{} overwritten with {new:02x?}",
+                    pos,
+                    pos + new.len()
+                )
+            }
+        }
+    }
+}
+
+/// `cases` inputs of each kind. Short outputs are truncated at, and flipped in, every position
+/// instead. The positions are seeded, so every run tries the same inputs.
+fn mutations(len: usize, cases: usize) -> Vec<Mutation> {
+    if len == 0 {
+        return Vec::new();
+    }
+    let mut rng = distributions::seeded_rng(distributions::SEED);
+    let mut mutations: Vec<Mutation> = if len <= cases {
+        (0..len).map(Mutation::Truncate).collect()
+    } else {
+        (0..cases)
+            .map(|i| Mutation::Truncate(i * len / cases))
+            .collect()
+    };
+    if len * 8 <= cases {
+        mutations.extend((0..len * 8).map(Mutation::FlipBit));
+    } else {
+        mutations.extend((0..cases).map(|_| Mutation::FlipBit(rng.gen_range(0..len * 8))));
+    }
+    for _ in 0..cases {
+        let pos = rng.gen_range(0..len);
+        let count = rng.gen_range(1..=4).min(len - pos);
+        mutations.push(Mutation::Overwrite(
+            pos,
+            (0..count).map(|_| rng.gen::<u8>()).collect(),
+        ));
+    }
+    mutations
+}
+
+/// What the decoder made of one corrupted input.
+enum Outcome {
+    Error,
+    Unchanged,
+    WrongValue,
+    Panic(String),
+    Abort(String),
+    /// With the size of the failed allocation.
+    OutOfMemory(String),
+    Timeout,
+}
+
+/// How the child reports an outcome, the ones it can't report itself are left out.
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Error => write!(f, "error"),
+            Outcome::Unchanged => write!(f, "unchanged"),
+            Outcome::WrongValue => write!(f, "wrong value"),
+            Outcome::Panic(panic) => write!(f, "panic: {}", first_line(panic)),
+            Outcome::Abort(_) | Outcome::OutOfMemory(_) | Outcome::Timeout => {
+                unreachable!("reported by the parent")
+            }
+        }
+    }
+}
+
+impl Outcome {
+    /// Reads what the child reported.
+    fn parse(line: &str) -> Option<Outcome> {
+        Some(match line {
+            "error" => Outcome::Error,
+            "unchanged" => Outcome::Unchanged,
+            "wrong value" => Outcome::WrongValue,
+            _ => Outcome::Panic(line.strip_prefix("panic: ")?.to_string()),
+        })
+    }
+}
+
+/// Checks every selected format on `test_struct`. In the child process, decodes the
+/// corrupted inputs of the format it was started for instead, and exits.
+pub fn test_scenario<T: Formats + PartialEq + Diff>(
+    name: &str,
+    test_struct: &T,
+    cli: &Cli,
+    config: &BenchConfig,
+) -> Vec<FormatResult> {
+    if let Some(format) = &cli.robustness_child {
+        if cli.robustness_scenario.as_deref() != Some(name) {
+            return Vec::new();
+        }
+        run_child(format, test_struct, config, cli.robustness_from);
+    }
+    formats::list(&Corrupt)
+        .into_iter()
+        .filter(|(format, _)| cli.runs_format(format))
+        .map(|(format, test_format)| match test_format {
+            Some(test_format) => test_format(test_struct, name, config),
+            None => FormatResult::skipped(format),
+        })
+        .collect()
+}
+
+/// Checks one format on a scenario, by name.
+type CorruptFn<T> = fn(&T, &str, &BenchConfig) -> FormatResult;
+
+/// Makes the [`CorruptFn`] of a format.
+struct Corrupt;
+
+impl<T: PartialEq + Diff> FormatVisitor<T> for Corrupt {
+    type Output = CorruptFn<T>;
+    fn visit<F: Serializes<T> + Deserializes<T>>(&self) -> CorruptFn<T> {
+        test_format::<T, F>
+    }
+}
+
+fn test_format<T: PartialEq + Diff, F: Serializes<T> + Deserializes<T>>(
+    test_struct: &T,
+    scenario: &str,
+    config: &BenchConfig,
+) -> FormatResult {
+    let (serialized_size, output) = match catch_panic(|| F::serialize(test_struct)) {
+        Ok(Ok(output)) => output,
+        Ok(Err(err)) => {
+            return FormatResult {
+                format: F::name(),
+                result: error_result::<F>("Ser Err", &err),
+                ..Default::default()
+            }
+        }
+        Err(panic) => {
+            return FormatResult {
+                format: F::name(),
+                result: format!("Ser Panic: {panic}"),
+                ..Default::default()
+            }
+        }
+    };
+    // Corrupting the output only says something if the intact output roundtrips.
+    let intact = match catch_panic(|| <F as Deserializes<T>>::deserialize(output)) {
+        Ok(Ok(deser)) => cmp_res(test_struct, &deser, config.float_cmp),
+        Ok(Err(err)) => {
+            return FormatResult {
+                format: F::name(),
+                serialized_size,
+                result: error_result::<F>("Deser Err", &err),
+                ..Default::default()
+            }
+        }
+        Err(panic) => {
+            return FormatResult {
+                format: F::name(),
+                serialized_size,
+                result: format!("Deser Panic: {panic}"),
+                ..Default::default()
+            }
+        }
+    };
+    if let Err(mismatches) = intact {
+        return FormatResult {
+            format: F::name(),
+            serialized_size,
+            result: "Cmp Mismatch".to_string(),
+            mismatches,
+            ..Default::default()
+        };
+    }
+
+    match decode_in_children(scenario, &F::name()) {
+        Ok(stats) => FormatResult {
+            format: F::name(),
+            serialized_size,
+            result: stats.result().to_string(),
+            corruption: Some(stats),
+            ..Default::default()
+        },
+        Err(err) => FormatResult {
+            format: F::name(),
+            serialized_size,
+            result: format!("Child Err: {err:?}"),
+            ..Default::default()
+        },
+    }
+}
+
+/// Decodes the corrupted inputs of `format` in child processes, starting a new one after each
+/// that died or hung.
+fn decode_in_children(scenario: &str, format: &str) -> Result<CorruptionStats> {
+    let mut stats = CorruptionStats::default();
+    let mut cases = None;
+    while cases.is_none_or(|cases| stats.cases < cases) {
+        let child = spawn_child(scenario, format, stats.cases)?;
+        // The input being decoded, only decoding is timed.
+        let mut decoding = None;
+        let timed_out = loop {
+            let timeout = decoding.is_some().then_some(HANG_TIMEOUT);
+            let line = match child.next_line(timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => break true,
+                Err(RecvTimeoutError::Disconnected) => break false,
+            };
+            if let Some(count) = line.strip_prefix(CASES_PREFIX) {
+                cases = Some(count.parse().context("invalid count of inputs")?);
+            } else if let Some(mutation) = line.strip_prefix(DECODING_PREFIX) {
+                decoding = Some(mutation.to_string());
+            } else if let Some(outcome) = line.strip_prefix(OUTCOME_PREFIX) {
+                let outcome = Outcome::parse(outcome)
+                    .with_context(|| format!("invalid outcome from the child: {outcome}"))?;
+                let mutation = decoding.take().context("outcome of no input")?;
+                stats.add(&mutation, outcome);
+            }
+        };
+        let output = if timed_out {
+            child.kill()?
+        } else {
+            child.wait()?
+        };
+        let Some(mutation) = decoding else {
+            if let Some(crash) = output.crash() {
+                bail!("the child failed outside of decoding: {crash}");
+            }
+            if cases.is_none() {
+                bail!("no inputs from the child: {}", output.stderr);
+            }
+            continue;
+        };
+        let outcome = match output.failed_allocation() {
+            _ if timed_out => Outcome::Timeout,
+            Some(bytes) => Outcome::OutOfMemory(bytes.to_string()),
+            None => Outcome::Abort(
+                output
+                    .crash()
+                    .unwrap_or_else(|| "exited while decoding".to_string()),
+            ),
+        };
+        stats.add(&mutation, outcome);
+    }
+    Ok(stats)
+}
+
+/// Starts a child on the same arguments, to decode the inputs of `format` from `from` on.
+fn spawn_child(scenario: &str, format: &str, from: usize) -> Result<RunningChild> {
+    let mut args: Vec<_> = std::env::args_os().skip(1).collect();
+    args.extend(
+        [
+            "--robustness-child",
+            format,
+            "--robustness-scenario",
+            scenario,
+            "--robustness-from",
+            &from.to_string(),
+        ]
+        .map(Into::into),
+    );
+    subprocess::spawn_self(args)
+}
+
+/// Entry point of the child process, decodes the inputs of `format` from `from` on and exits.
+/// The parent only reads the reports, so the rest of the run is skipped.
+fn run_child<T: Formats + PartialEq + Diff>(
+    format: &str,
+    test_struct: &T,
+    config: &BenchConfig,
+    from: usize,
+) -> ! {
+    let res = match formats::list(&Decode)
+        .into_iter()
+        .find(|(name, _)| name == format)
+    {
+        Some((_, Some(decode))) => decode(test_struct, config, from),
+        _ => Err(anyhow::anyhow!("unknown format {format}")),
+    };
+    if let Err(err) = res {
+        eprintln!("Error: {err:?}");
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+/// Decodes the corrupted inputs of one format from the given one on, in the child.
+type DecodeFn<T> = fn(&T, &BenchConfig, usize) -> Result<()>;
+
+/// Makes the [`DecodeFn`] of a format.
+struct Decode;
+
+impl<T: PartialEq + Diff> FormatVisitor<T> for Decode {
+    type Output = DecodeFn<T>;
+    fn visit<F: Serializes<T> + Deserializes<T>>(&self) -> DecodeFn<T> {
+        decode_corrupted::<T, F>
+    }
+}
+
+/// Reports the number of inputs, then each input before it is decoded and its outcome after.
+fn decode_corrupted<T: PartialEq + Diff, F: Serializes<T> + Deserializes<T>>(
+    test_struct: &T,
+    config: &BenchConfig,
+    from: usize,
+) -> Result<()> {
+    let (_, output) = F::serialize(test_struct).context("could not serialize")?;
+    let bytes = output.to_raw();
+    let mutations = mutations(bytes.len(), config.corruptions);
+    println!("{CASES_PREFIX}{}", mutations.len());
+    for mutation in mutations.iter().skip(from) {
+        println!("{DECODING_PREFIX}{mutation}");
+        let input = F::Serialized::from_raw(mutation.apply(&bytes));
+        let outcome = match catch_panic(|| <F as Deserializes<T>>::deserialize(input)) {
+            Ok(Ok(deser)) if cmp_res(test_struct, &deser, config.float_cmp).is_ok() => {
+                Outcome::Unchanged
+            }
+            Ok(Ok(_)) => Outcome::WrongValue,
+            Ok(Err(_)) => Outcome::Error,
+            Err(panic) => Outcome::Panic(panic),
+        };
+        println!("{OUTCOME_PREFIX}{outcome}");
+    }
+    Ok(())
+}
+
+/// One row per format with the count of each outcome, and an example input of each bad one.
+pub fn print_table(scenario: &ScenarioResult, output: OutputFormat) {
+    let mut table = get_table(output);
+    let mut row = Row::empty();
+    for title in [
+        "Format",
+        "Result",
+        "Serialized Size",
+        "Inputs",
+        "Error",
+        "Unchanged",
+        "Wrong Value",
+        "Panic",
+        "Abort",
+        "OOM",
+        "Timeout",
+    ] {
+        row.add_cell(cell!(title));
+    }
+    table.set_titles(row);
+    for res in &scenario.results {
+        let mut row = Row::empty();
+        row.add_cell(cell!(res.format));
+        row.add_cell(cell!(first_line(&res.result)));
+        if res.is_skipped() {
+            table.add_row(row);
+            continue;
+        }
+        row.add_cell(cell!(res.serialized_size));
+        if let Some(stats) = &res.corruption {
+            for count in [
+                stats.cases,
+                stats.errors,
+                stats.unchanged,
+                stats.wrong_values,
+                stats.panics,
+                stats.aborts,
+                stats.out_of_memory,
+                stats.timeouts,
+            ] {
+                row.add_cell(cell!(count));
+            }
+        }
+        table.add_row(row);
+    }
+    table.printstd();
+    for res in &scenario.results {
+        for example in res.corruption.iter().flat_map(|stats| &stats.examples) {
+            println!("{}: {example}", res.format);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_outputs_get_every_position() {
+        let mutations = mutations(2, 100);
+        let truncated: Vec<usize> = mutations
+            .iter()
+            .filter_map(|mutation| match mutation {
+                Mutation::Truncate(len) => Some(*len),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(truncated, [0, 1]);
+        let flipped = mutations
+            .iter()
+            .filter(|mutation| matches!(mutation, Mutation::FlipBit(_)))
+            .count();
+        assert_eq!(flipped, 16);
+    }
+
+    #[test]
+    fn long_outputs_get_cases_of_each_kind_in_bounds() {
+        let len = 1000;
+        let mutations = mutations(len, 10);
+        assert_eq!(mutations.len(), 30);
+        for mutation in &mutations {
+            match mutation {
+                Mutation::Truncate(truncated) => assert!(*truncated < len),
+                Mutation::FlipBit(bit) => assert!(*bit < len * 8),
+                Mutation::Overwrite(pos, new) => {
+                    assert!((1..=4).contains(&new.len()) && pos + new.len() <= len)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mutations_are_seeded() {
+        let describe = |mutations: Vec<Mutation>| -> Vec<String> {
+            mutations.iter().map(ToString::to_string).collect()
+        };
+        assert_eq!(describe(mutations(500, 20)), describe(mutations(500, 20)));
+    }
+
+    #[test]
+    fn applies_mutations() {
+        let bytes = [0, 0, 0];
+        assert_eq!(Mutation::Truncate(1).apply(&bytes), [0]);
+        assert_eq!(Mutation::FlipBit(9).apply(&bytes), [0, 2, 0]);
+        assert_eq!(Mutation::Overwrite(1, vec![7, 8]).apply(&bytes), [0, 7, 8]);
+    }
+
+    #[test]
+    fn empty_outputs_get_no_mutations() {
+        assert!(mutations(0, 100).is_empty());
+    }
+}
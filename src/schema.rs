@@ -0,0 +1,499 @@
+//! Infers a concrete schema from JSON samples, so formats that need one can be compared on data
+//! nobody wrote a struct for.
+//!
+//! Objects become structs with their fields in the order they were first seen, fields missing in
+//! some samples or sometimes `null` become options. Values whose types conflict, e.g. a field that
+//! is a string in one sample and an object in another, fall back to their JSON text.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use serde::{
+    de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeSeq, SerializeStruct},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Map, Value};
+
+use crate::formats::impl_formats;
+
+/// Name of all inferred structs, formats that write struct names get the same one everywhere.
+const STRUCT_NAME: &str = "Inferred";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schema {
+    /// The elements of empty arrays.
+    Unknown,
+    Null,
+    Bool,
+    Int,
+    /// Integers beyond `i64::MAX`.
+    UInt,
+    Float,
+    String,
+    /// Conflicting types, stored as their JSON text.
+    Json,
+    Optional(Box<Schema>),
+    Array(Box<Schema>),
+    Struct {
+        fields: &'static [&'static str],
+        types: Vec<Schema>,
+    },
+}
+
+impl Schema {
+    /// The schema all `samples` conform to.
+    pub fn infer<'a>(samples: impl IntoIterator<Item = &'a Value>) -> Schema {
+        samples.into_iter().fold(Schema::Unknown, |schema, sample| {
+            schema.merge(Schema::of(sample))
+        })
+    }
+
+    fn of(value: &Value) -> Schema {
+        match value {
+            Value::Null => Schema::Null,
+            Value::Bool(_) => Schema::Bool,
+            Value::Number(num) if num.is_i64() => Schema::Int,
+            Value::Number(num) if num.is_u64() => Schema::UInt,
+            Value::Number(_) => Schema::Float,
+            Value::String(_) => Schema::String,
+            Value::Array(values) => Schema::Array(Box::new(Schema::infer(values))),
+            Value::Object(map) => Schema::Struct {
+                fields: intern_fields(map.keys().map(|key| intern(key)).collect()),
+                types: map.values().map(Schema::of).collect(),
+            },
+        }
+    }
+
+    fn merge(self, other: Schema) -> Schema {
+        use Schema::*;
+        match (self, other) {
+            (Unknown, other) | (other, Unknown) => other,
+            (Null, Null) => Null,
+            (Null, Optional(inner)) | (Optional(inner), Null) => Optional(inner),
+            (Null, other) | (other, Null) => Optional(Box::new(other)),
+            (Optional(a), Optional(b)) => Optional(Box::new(a.merge(*b))),
+            (Optional(a), b) | (b, Optional(a)) => Optional(Box::new(a.merge(b))),
+            (a, b) if a == b => a,
+            (Int | UInt | Float, Int | UInt | Float) => Float,
+            (Array(a), Array(b)) => Array(Box::new(a.merge(*b))),
+            (
+                Struct { fields, types },
+                Struct {
+                    fields: other_fields,
+                    types: other_types,
+                },
+            ) => merge_structs(fields, types, other_fields, other_types),
+            _ => Json,
+        }
+    }
+
+    /// Converts `value` to the exact shape the schema deserializes to, so roundtrips compare
+    /// equal: integers in float fields become floats, missing fields `null` and conflicting
+    /// values their JSON text.
+    pub fn conform(&self, value: &Value) -> Value {
+        match (self, value) {
+            (Schema::Float, Value::Number(num)) => num.as_f64().map_or(Value::Null, Value::from),
+            (Schema::Json, value) => Value::String(value.to_string()),
+            (Schema::Optional(_), Value::Null) => Value::Null,
+            (Schema::Optional(inner), value) => inner.conform(value),
+            (Schema::Array(inner), Value::Array(values)) => {
+                Value::Array(values.iter().map(|value| inner.conform(value)).collect())
+            }
+            (Schema::Struct { fields, types }, Value::Object(map)) => Value::Object(
+                fields
+                    .iter()
+                    .zip(types)
+                    .map(|(field, schema)| {
+                        let value = map.get(*field).unwrap_or(&Value::Null);
+                        (field.to_string(), schema.conform(value))
+                    })
+                    .collect(),
+            ),
+            (_, value) => value.clone(),
+        }
+    }
+}
+
+/// Fields of both structs, fields only in one of them become optional.
+fn merge_structs(
+    fields: &'static [&'static str],
+    mut types: Vec<Schema>,
+    other_fields: &'static [&'static str],
+    other_types: Vec<Schema>,
+) -> Schema {
+    let mut merged_fields = fields.to_vec();
+    let mut in_other = vec![false; fields.len()];
+    for (field, other) in other_fields.iter().zip(other_types) {
+        match fields.iter().position(|existing| existing == field) {
+            Some(pos) => {
+                in_other[pos] = true;
+                let schema = std::mem::replace(&mut types[pos], Schema::Unknown);
+                types[pos] = schema.merge(other);
+            }
+            None => {
+                merged_fields.push(field);
+                types.push(other.merge(Schema::Null));
+            }
+        }
+    }
+    for (schema, in_other) in types.iter_mut().zip(in_other) {
+        if !in_other {
+            *schema = std::mem::replace(schema, Schema::Unknown).merge(Schema::Null);
+        }
+    }
+    Schema::Struct {
+        fields: intern_fields(merged_fields),
+        types,
+    }
+}
+
+// serde wants field names as `&'static str`. They are leaked, but only once per distinct name or
+// list of fields, which is bounded by the input.
+static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+static FIELD_LISTS: Mutex<BTreeSet<&'static [&'static str]>> = Mutex::new(BTreeSet::new());
+
+fn intern(name: &str) -> &'static str {
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name) {
+        return name;
+    }
+    let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+    names.insert(name);
+    name
+}
+
+fn intern_fields(fields: Vec<&'static str>) -> &'static [&'static str] {
+    let mut lists = FIELD_LISTS.lock().unwrap();
+    if let Some(fields) = lists.get(fields.as_slice()) {
+        return fields;
+    }
+    let fields: &'static [&'static str] = Box::leak(fields.into_boxed_slice());
+    lists.insert(fields);
+    fields
+}
+
+/// Elements reserved up front for a sequence, at most 1 MiB.
+const MAX_PREALLOCATED: usize = 1024 * 1024 / std::mem::size_of::<Value>();
+
+thread_local! {
+    /// The schema [`Inferred`] values are deserialized with, see [`with_schema`].
+    static SCHEMA: RefCell<Option<Arc<Schema>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `schema` as the schema to deserialize [`Inferred`] values with.
+///
+/// `Deser` only knows `DeserializeOwned` types, so the schema can't be passed as a seed.
+pub fn with_schema<R>(schema: Arc<Schema>, f: impl FnOnce() -> R) -> R {
+    let previous = SCHEMA.with(|current| current.replace(Some(schema)));
+    let res = f();
+    SCHEMA.with(|current| current.replace(previous));
+    res
+}
+
+/// A JSON value with its inferred schema, it serializes as if it were a struct generated from the
+/// schema.
+#[derive(Debug, Clone)]
+pub struct Inferred {
+    schema: Arc<Schema>,
+    value: Value,
+}
+
+impl Inferred {
+    /// `value` must conform to `schema`, see [`Schema::conform`].
+    pub fn new(schema: Arc<Schema>, value: Value) -> Self {
+        Self { schema, value }
+    }
+}
+
+impl PartialEq for Inferred {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl Serialize for Inferred {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Typed(&self.schema, &self.value).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Inferred {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let schema = SCHEMA
+            .with(|schema| schema.borrow().clone())
+            .ok_or_else(|| de::Error::custom("no schema set to deserialize inferred values"))?;
+        let value = TypedSeed(&schema).deserialize(deserializer)?;
+        Ok(Inferred { schema, value })
+    }
+}
+
+// Like `serde_json::Value`, inferred values only go through serde.
+impl_formats!(Inferred: serde);
+
+/// A value serialized according to a schema.
+struct Typed<'a>(&'a Schema, &'a Value);
+
+impl Serialize for Typed<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+        let mismatch = || S::Error::custom(format!("{} does not match {:?}", self.1, self.0));
+        match (self.0, self.1) {
+            (Schema::Unknown | Schema::Null, _) => serializer.serialize_unit(),
+            (Schema::Bool, Value::Bool(val)) => serializer.serialize_bool(*val),
+            (Schema::Int, Value::Number(num)) => {
+                serializer.serialize_i64(num.as_i64().ok_or_else(mismatch)?)
+            }
+            (Schema::UInt, Value::Number(num)) => {
+                serializer.serialize_u64(num.as_u64().ok_or_else(mismatch)?)
+            }
+            (Schema::Float, Value::Number(num)) => {
+                serializer.serialize_f64(num.as_f64().ok_or_else(mismatch)?)
+            }
+            (Schema::String | Schema::Json, Value::String(text)) => serializer.serialize_str(text),
+            (Schema::Optional(_), Value::Null) => serializer.serialize_none(),
+            (Schema::Optional(inner), value) => serializer.serialize_some(&Typed(inner, value)),
+            (Schema::Array(inner), Value::Array(values)) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(&Typed(inner, value))?;
+                }
+                seq.end()
+            }
+            (Schema::Struct { fields, types }, Value::Object(map)) => {
+                let mut st = serializer.serialize_struct(STRUCT_NAME, fields.len())?;
+                for (field, schema) in fields.iter().zip(types) {
+                    let value = map.get(*field).ok_or_else(mismatch)?;
+                    st.serialize_field(field, &Typed(schema, value))?;
+                }
+                st.end()
+            }
+            _ => Err(mismatch()),
+        }
+    }
+}
+
+/// Deserializes a value according to a schema.
+#[derive(Clone, Copy)]
+struct TypedSeed<'a>(&'a Schema);
+
+impl<'de> DeserializeSeed<'de> for TypedSeed<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        match self.0 {
+            Schema::Unknown | Schema::Null => deserializer.deserialize_unit(self),
+            Schema::Bool => deserializer.deserialize_bool(self),
+            Schema::Int => deserializer.deserialize_i64(self),
+            Schema::UInt => deserializer.deserialize_u64(self),
+            Schema::Float => deserializer.deserialize_f64(self),
+            Schema::String | Schema::Json => deserializer.deserialize_string(self),
+            Schema::Optional(_) => deserializer.deserialize_option(self),
+            Schema::Array(_) => deserializer.deserialize_seq(self),
+            Schema::Struct { fields, .. } => {
+                deserializer.deserialize_struct(STRUCT_NAME, fields, self)
+            }
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for TypedSeed<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value of schema {:?}", self.0)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        match self.0 {
+            Schema::Optional(inner) => TypedSeed(inner).deserialize(deserializer),
+            _ => Err(de::Error::invalid_type(de::Unexpected::Option, &self)),
+        }
+    }
+
+    fn visit_bool<E: de::Error>(self, val: bool) -> Result<Value, E> {
+        Ok(Value::Bool(val))
+    }
+
+    fn visit_i64<E: de::Error>(self, val: i64) -> Result<Value, E> {
+        Ok(match self.0 {
+            Schema::Float => Value::from(val as f64),
+            _ => Value::from(val),
+        })
+    }
+
+    fn visit_u64<E: de::Error>(self, val: u64) -> Result<Value, E> {
+        Ok(match self.0 {
+            Schema::Float => Value::from(val as f64),
+            _ => Value::from(val),
+        })
+    }
+
+    fn visit_f64<E: de::Error>(self, val: f64) -> Result<Value, E> {
+        Ok(Value::from(val))
+    }
+
+    fn visit_str<E: de::Error>(self, val: &str) -> Result<Value, E> {
+        Ok(Value::String(val.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, val: String) -> Result<Value, E> {
+        Ok(Value::String(val))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        match self.0 {
+            Schema::Array(inner) => {
+                // The hint comes from the input, like serde's own impls don't trust it blindly.
+                let hint = seq.size_hint().unwrap_or_default();
+                let mut values = Vec::with_capacity(hint.min(MAX_PREALLOCATED));
+                while let Some(value) = seq.next_element_seed(TypedSeed(inner))? {
+                    values.push(value);
+                }
+                Ok(Value::Array(values))
+            }
+            // Formats without field names write structs as sequences.
+            Schema::Struct { fields, types } => {
+                let mut map = Map::new();
+                for (i, (field, schema)) in fields.iter().zip(types).enumerate() {
+                    let value = seq
+                        .next_element_seed(TypedSeed(schema))?
+                        .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+                    map.insert(field.to_string(), value);
+                }
+                Ok(Value::Object(map))
+            }
+            _ => Err(de::Error::invalid_type(de::Unexpected::Seq, &self)),
+        }
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Value, A::Error> {
+        let Schema::Struct { fields, types } = self.0 else {
+            return Err(de::Error::invalid_type(de::Unexpected::Map, &self));
+        };
+        let mut map = Map::new();
+        while let Some(pos) = access.next_key_seed(FieldSeed(fields))? {
+            let value = access.next_value_seed(TypedSeed(&types[pos]))?;
+            map.insert(fields[pos].to_string(), value);
+        }
+        for field in fields.iter() {
+            if !map.contains_key(*field) {
+                return Err(de::Error::missing_field(field));
+            }
+        }
+        Ok(Value::Object(map))
+    }
+}
+
+/// Deserializes a field name of a struct to its index.
+struct FieldSeed(&'static [&'static str]);
+
+impl<'de> DeserializeSeed<'de> for FieldSeed {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+        deserializer.deserialize_identifier(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FieldSeed {
+    type Value = usize;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "one of the fields {:?}", self.0)
+    }
+
+    fn visit_u64<E: de::Error>(self, index: u64) -> Result<usize, E> {
+        usize::try_from(index)
+            .ok()
+            .filter(|index| *index < self.0.len())
+            .ok_or_else(|| E::invalid_value(de::Unexpected::Unsigned(index), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<usize, E> {
+        self.0
+            .iter()
+            .position(|field| *field == name)
+            .ok_or_else(|| E::unknown_field(name, self.0))
+    }
+
+    fn visit_bytes<E: de::Error>(self, name: &[u8]) -> Result<usize, E> {
+        let name = std::str::from_utf8(name)
+            .map_err(|_| E::invalid_value(de::Unexpected::Bytes(name), &self))?;
+        self.visit_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn infer(samples: Value) -> Schema {
+        Schema::infer(samples.as_array().unwrap())
+    }
+
+    #[test]
+    fn merges_numbers() {
+        assert_eq!(infer(json!([1, -2])), Schema::Int);
+        assert_eq!(infer(json!([u64::MAX, u64::MAX])), Schema::UInt);
+        assert_eq!(infer(json!([1.5, 2.5])), Schema::Float);
+        assert_eq!(infer(json!([1, u64::MAX])), Schema::Float);
+        assert_eq!(infer(json!([u64::MAX, 1.5])), Schema::Float);
+        assert_eq!(infer(json!([1, 1.5])), Schema::Float);
+    }
+
+    #[test]
+    fn merges_numbers_inside_options_and_arrays() {
+        assert_eq!(
+            infer(json!([1, null, 1.5])),
+            Schema::Optional(Box::new(Schema::Float))
+        );
+        assert_eq!(
+            infer(json!([[1], [], [u64::MAX]])),
+            Schema::Array(Box::new(Schema::Float))
+        );
+    }
+
+    #[test]
+    fn conflicting_types_become_json() {
+        assert_eq!(infer(json!([1, "1"])), Schema::Json);
+    }
+
+    #[test]
+    fn fields_missing_in_some_samples_become_optional() {
+        let Schema::Struct { fields, types } = infer(json!([{"a": 1}, {"a": 1.5, "b": true}]))
+        else {
+            panic!("not a struct");
+        };
+        assert_eq!(fields, ["a", "b"]);
+        assert_eq!(
+            types,
+            [Schema::Float, Schema::Optional(Box::new(Schema::Bool))]
+        );
+    }
+
+    #[test]
+    fn conforms_integers_to_float_fields() {
+        let samples = json!([{"a": 1}, {"a": 1.5}]);
+        let schema = infer(samples.clone());
+        assert_eq!(schema.conform(&samples[0]), json!({"a": 1.0}));
+        assert_eq!(
+            infer(json!([1, "1"])).conform(&json!(1)),
+            Value::String("1".to_string())
+        );
+    }
+}
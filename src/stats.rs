@@ -0,0 +1,172 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Number of resamples drawn for the bootstrap confidence interval.
+const BOOTSTRAP_RESAMPLES: usize = 1_000;
+/// Fixed seed, so that repeated runs over the same samples report the same interval.
+const BOOTSTRAP_SEED: u64 = 0x5eed;
+/// Samples with a modified z-score above this are rejected as outliers (Iglewicz and Hoaglin).
+const OUTLIER_Z_SCORE: f64 = 3.5;
+
+/// Summary of the measured iterations of one phase, in nanoseconds.
+///
+/// `min`, `mean` and `p99` are computed over all samples. The median and its confidence interval
+/// are computed after rejecting outliers, `outliers` is the number of rejected samples.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct TimingStats {
+    pub min: u128,
+    pub median: u128,
+    pub mean: u128,
+    pub p99: u128,
+    /// Lower bound of the 95% bootstrap confidence interval of the median.
+    pub ci_low: u128,
+    /// Upper bound of the 95% bootstrap confidence interval of the median.
+    pub ci_high: u128,
+    pub outliers: usize,
+}
+
+impl TimingStats {
+    pub fn from_samples(samples: &[u128]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+
+        let inliers = reject_outliers(&sorted);
+        let (ci_low, ci_high) = bootstrap_median_ci(&inliers);
+
+        Self {
+            min: sorted[0],
+            median: percentile(&inliers, 50.0),
+            mean: sorted.iter().sum::<u128>() / sorted.len() as u128,
+            p99: percentile(&sorted, 99.0),
+            ci_low,
+            ci_high,
+            outliers: sorted.len() - inliers.len(),
+        }
+    }
+
+    /// Throughput in MB (10^6 bytes) of serialized output per second, based on the median.
+    pub fn megabytes_per_sec(&self, bytes: usize) -> f64 {
+        self.ops_per_sec() * bytes as f64 / 1_000_000.0
+    }
+
+    /// Throughput in processed objects per second, based on the median.
+    pub fn ops_per_sec(&self) -> f64 {
+        if self.median == 0 {
+            return 0.0;
+        }
+        1_000_000_000.0 / self.median as f64
+    }
+
+    /// Returns true if the confidence intervals of both medians overlap, i.e. the difference
+    /// between them is not significant.
+    pub fn overlaps(&self, other: &TimingStats) -> bool {
+        self.ci_low <= other.ci_high && other.ci_low <= self.ci_high
+    }
+}
+
+/// Nearest-rank percentile over already sorted samples.
+fn percentile(sorted: &[u128], p: f64) -> u128 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Removes samples that are too far from the median, measured in median absolute deviations.
+///
+/// Expects sorted samples and keeps them sorted.
+fn reject_outliers(sorted: &[u128]) -> Vec<u128> {
+    let median = percentile(sorted, 50.0) as f64;
+    let mut deviations: Vec<u128> = sorted
+        .iter()
+        .map(|&sample| (sample as f64 - median).abs() as u128)
+        .collect();
+    deviations.sort_unstable();
+    let mad = percentile(&deviations, 50.0) as f64;
+    if mad == 0.0 {
+        return sorted.to_vec();
+    }
+
+    sorted
+        .iter()
+        .copied()
+        .filter(|&sample| 0.6745 * (sample as f64 - median).abs() / mad <= OUTLIER_Z_SCORE)
+        .collect()
+}
+
+/// 95% confidence interval of the median via the percentile bootstrap.
+fn bootstrap_median_ci(samples: &[u128]) -> (u128, u128) {
+    let mut rng = StdRng::seed_from_u64(BOOTSTRAP_SEED);
+    let mut resample = vec![0; samples.len()];
+    let mut medians: Vec<u128> = (0..BOOTSTRAP_RESAMPLES)
+        .map(|_| {
+            for slot in resample.iter_mut() {
+                *slot = samples[rng.gen_range(0..samples.len())];
+            }
+            resample.sort_unstable();
+            percentile(&resample, 50.0)
+        })
+        .collect();
+    medians.sort_unstable();
+    (percentile(&medians, 2.5), percentile(&medians, 97.5))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentile_is_nearest_rank() {
+        let sorted: Vec<u128> = (1..=10).collect();
+        assert_eq!(percentile(&sorted, 0.0), 1);
+        assert_eq!(percentile(&sorted, 50.0), 5);
+        assert_eq!(percentile(&sorted, 51.0), 6);
+        assert_eq!(percentile(&sorted, 99.0), 10);
+        assert_eq!(percentile(&sorted, 100.0), 10);
+        assert_eq!(percentile(&[7], 50.0), 7);
+    }
+
+    #[test]
+    fn rejects_samples_far_from_the_median() {
+        // Median 12, MAD 1, so 1000 has a modified z-score of ~666 and 10 one of ~1.35.
+        assert_eq!(
+            reject_outliers(&[10, 11, 12, 13, 1000]),
+            vec![10, 11, 12, 13]
+        );
+    }
+
+    #[test]
+    fn keeps_all_samples_if_the_mad_is_zero() {
+        assert_eq!(reject_outliers(&[5, 5, 5, 100]), vec![5, 5, 5, 100]);
+    }
+
+    #[test]
+    fn median_and_ci_without_outliers_the_rest_over_all_samples() {
+        let stats = TimingStats::from_samples(&[13, 1000, 11, 10, 12]);
+        assert_eq!(stats.min, 10);
+        assert_eq!(stats.median, 11);
+        assert_eq!(stats.mean, 1046 / 5);
+        assert_eq!(stats.p99, 1000);
+        assert_eq!(stats.outliers, 1);
+        assert!(stats.ci_low >= 10 && stats.ci_high <= 13);
+    }
+
+    #[test]
+    fn ci_of_constant_samples_is_the_value() {
+        let stats = TimingStats::from_samples(&[42; 20]);
+        assert_eq!((stats.ci_low, stats.ci_high), (42, 42));
+    }
+
+    #[test]
+    fn overlapping_intervals() {
+        let stats = |ci_low, ci_high| TimingStats {
+            ci_low,
+            ci_high,
+            ..TimingStats::default()
+        };
+        assert!(stats(10, 20).overlaps(&stats(20, 30)));
+        assert!(stats(10, 40).overlaps(&stats(20, 30)));
+        assert!(!stats(10, 19).overlaps(&stats(20, 30)));
+    }
+}
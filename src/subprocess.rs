@@ -0,0 +1,153 @@
+//! Runs this binary again in a child process, for tests that can take down the whole process,
+//! like a stack overflow.
+
+use std::{
+    ffi::OsStr,
+    io::{BufRead, BufReader, Read},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+pub struct ChildOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ChildOutput {
+    /// How the child died, `None` if it exited with success.
+    pub fn crash(&self) -> Option<String> {
+        if self.status.success() {
+            return None;
+        }
+        if let Some(bytes) = self.failed_allocation() {
+            return Some(format!("Alloc Failed: {bytes} bytes"));
+        }
+        if self.stderr.contains("has overflowed its stack") {
+            return Some("Stack overflow".to_string());
+        }
+        if let Some(panic) = self
+            .stderr
+            .lines()
+            .skip_while(|line| !line.contains("panicked at"))
+            .nth(1)
+        {
+            return Some(format!("Panic: {}", panic.trim()));
+        }
+        Some(match signal(&self.status) {
+            Some(signal) => format!("Killed by signal {signal}"),
+            None => format!("Exited with {}", self.status),
+        })
+    }
+
+    /// The size of the allocation that aborted the child. Rust prints "memory allocation of N
+    /// bytes failed" before it aborts.
+    pub fn failed_allocation(&self) -> Option<&str> {
+        self.stderr
+            .lines()
+            .find_map(|line| line.strip_prefix("memory allocation of "))
+            .and_then(|rest| rest.strip_suffix(" bytes failed"))
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Runs the current executable with `args` and waits for it.
+pub fn run_self(args: &[String]) -> Result<ChildOutput> {
+    let exe = std::env::current_exe().context("could not find the current executable")?;
+    let output = Command::new(&exe)
+        .args(args)
+        .output()
+        .with_context(|| format!("could not run {}", exe.display()))?;
+    Ok(ChildOutput {
+        status: output.status,
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// A child started with [`spawn_self`], its stdout is read line by line while it runs.
+pub struct RunningChild {
+    child: Child,
+    lines: Receiver<String>,
+    stderr: JoinHandle<String>,
+}
+
+impl RunningChild {
+    /// The next line the child printed, waiting at most `timeout` if given.
+    /// [`RecvTimeoutError::Disconnected`] once the child closed its stdout, usually by exiting.
+    pub fn next_line(&self, timeout: Option<Duration>) -> Result<String, RecvTimeoutError> {
+        match timeout {
+            Some(timeout) => self.lines.recv_timeout(timeout),
+            None => self
+                .lines
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// Kills the child, e.g. because it hangs, and waits for it.
+    pub fn kill(mut self) -> Result<ChildOutput> {
+        // Fails only if the child already exited.
+        let _ = self.child.kill();
+        self.wait()
+    }
+
+    /// Waits for the child to exit. `stdout` has the lines that weren't read yet.
+    pub fn wait(mut self) -> Result<ChildOutput> {
+        let status = self.child.wait().context("could not wait for the child")?;
+        let stdout = self.lines.iter().map(|line| line + "\n").collect();
+        let stderr = self.stderr.join().unwrap_or_default();
+        Ok(ChildOutput {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+/// Starts the current executable with `args`, without waiting for it.
+pub fn spawn_self(args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Result<RunningChild> {
+    let exe = std::env::current_exe().context("could not find the current executable")?;
+    let mut child = Command::new(&exe)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("could not run {}", exe.display()))?;
+    let stdout = child.stdout.take().context("no stdout of the child")?;
+    let mut stderr = child.stderr.take().context("no stderr of the child")?;
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            // The receiver is gone once the child was waited for.
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let stderr = thread::spawn(move || {
+        let mut text = Vec::new();
+        let _ = stderr.read_to_end(&mut text);
+        String::from_utf8_lossy(&text).into_owned()
+    });
+    Ok(RunningChild {
+        child,
+        lines,
+        stderr,
+    })
+}
@@ -0,0 +1,137 @@
+//! Runs the seeded generators at growing sizes, to see how size and time scale with the number of
+//! buckets.
+
+use anyhow::{bail, Result};
+use prettytable::{cell, Row, Table};
+
+use crate::{
+    cli::{Cli, OutputFormat},
+    distributions::SEED,
+    first_line, get_table, test_scenario_with,
+    test_struct_agg1::generate_histogram,
+    test_struct_agg2::generate_terms,
+    BenchConfig, ScenarioResult,
+};
+
+/// A seeded test data generator, scaled by the number of buckets.
+struct Generator {
+    name: &'static str,
+    run: fn(&str, usize, &Cli, &BenchConfig) -> ScenarioResult,
+}
+
+fn generators() -> Vec<Generator> {
+    vec![
+        Generator {
+            name: "Histogram Generated",
+            run: |name, buckets, cli, config| {
+                test_scenario_with(name, generate_histogram(buckets, SEED), cli, config)
+            },
+        },
+        Generator {
+            name: "Terms Generated",
+            run: |name, buckets, cli, config| {
+                test_scenario_with(name, generate_terms(buckets, SEED), cli, config)
+            },
+        },
+    ]
+}
+
+/// Bucket counts of the sweep, powers of ten from 10 up to `max`.
+fn sizes(max: usize) -> impl Iterator<Item = usize> {
+    std::iter::successors(Some(10usize), |size| size.checked_mul(10))
+        .take_while(move |size| *size <= max)
+}
+
+/// Above 10,000 buckets the iterations are reduced in proportion, so a sweep to a million buckets
+/// finishes in minutes. At least 3 iterations are measured.
+fn config(cli: &Cli, buckets: usize) -> BenchConfig {
+    let config = cli.bench_config();
+    let scale = (buckets / 10_000).max(1);
+    BenchConfig {
+        warmup: config.warmup / scale,
+        iterations: (config.iterations / scale).max(3),
+        ..config
+    }
+}
+
+/// Runs all generators selected with `--scenario` at all sizes, and prints a summary per
+/// generator.
+pub fn run(cli: &Cli) -> Result<Vec<ScenarioResult>> {
+    let selected: Vec<Generator> = generators()
+        .into_iter()
+        .filter(|generator| cli.runs_scenario(generator.name))
+        .collect();
+    if selected.is_empty() {
+        bail!("no generator matches {:?}", cli.scenarios);
+    }
+
+    let mut results = Vec::new();
+    for generator in selected {
+        let mut runs = Vec::new();
+        for buckets in sizes(cli.sweep_max) {
+            let name = format!("{} ({} buckets)", generator.name, buckets);
+            let res = (generator.run)(&name, buckets, cli, &config(cli, buckets));
+            runs.push((buckets, res));
+        }
+        if cli.output.is_table() {
+            println!("Sweep: {}", generator.name);
+            summary_table(&runs, cli.output).printstd();
+        }
+        results.extend(runs.into_iter().map(|(_, res)| res));
+    }
+    Ok(results)
+}
+
+/// One row per format and size, grouped by format so the growth of each format reads top down.
+fn summary_table(runs: &[(usize, ScenarioResult)], output: OutputFormat) -> Table {
+    let mut table = get_table(output);
+    let mut row = Row::empty();
+    row.add_cell(cell!["Format"]);
+    row.add_cell(cell!["Buckets"]);
+    row.add_cell(cell!["Result"]);
+    row.add_cell(cell!["Serialized Size"]);
+    row.add_cell(cell!["Bytes/Bucket"]);
+    row.add_cell(cell!["Serialize Inlier Median [ns]"]);
+    row.add_cell(cell!["Deserialize Inlier Median [ns]"]);
+    row.add_cell(cell!["Roundtrip Inlier Median [ns]"]);
+    row.add_cell(cell!["Roundtrip [ns/Bucket]"]);
+    table.set_titles(row);
+
+    let formats = runs
+        .first()
+        .map(|(_, res)| res.results.iter().map(|format| format.format.as_str()))
+        .into_iter()
+        .flatten();
+    for format in formats {
+        for (buckets, scenario) in runs {
+            let Some(res) = scenario.results.iter().find(|res| res.format == format) else {
+                continue;
+            };
+            let mut row = Row::empty();
+            row.add_cell(cell!(res.format));
+            row.add_cell(cell!(buckets));
+            row.add_cell(cell!(first_line(&res.result)));
+            if !res.is_measured() {
+                if res.serialized_size > 0 {
+                    row.add_cell(cell!(res.serialized_size));
+                }
+                table.add_row(row);
+                continue;
+            }
+            row.add_cell(cell!(res.serialized_size));
+            row.add_cell(cell!(format!(
+                "{:.1}",
+                res.serialized_size as f64 / *buckets as f64
+            )));
+            row.add_cell(cell!(res.serialize_time.median));
+            row.add_cell(cell!(res.deserialize_time.median));
+            row.add_cell(cell!(res.roundtrip_time.median));
+            row.add_cell(cell!(format!(
+                "{:.1}",
+                res.roundtrip_time.median as f64 / *buckets as f64
+            )));
+            table.add_row(row);
+        }
+    }
+    table
+}
@@ -1,8 +1,19 @@
+use minicbor::{Decode, Encode};
+use proptest::{
+    collection, option,
+    prelude::{any, BoxedStrategy, Strategy},
+    prop_oneof,
+    sample::select,
+};
+use rand::{rngs::StdRng, Rng};
 use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 use std::{collections::HashMap, fmt::Debug};
 
+use crate::{distributions, formats::impl_formats, fuzz::SubAggregations};
+
 /// An aggregation is either a bucket or a metric.
+#[allow(dead_code)]
 #[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
 pub enum IntermediateAggregationResult {
     /// Bucket variant
@@ -12,12 +23,14 @@ pub enum IntermediateAggregationResult {
 }
 
 /// Holds the intermediate data for metric results
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub enum IntermediateMetricResult {
     /// Intermediate average result.
-    Percentiles(PercentilesCollector),
+    #[n(0)]
+    Percentiles(#[n(0)] PercentilesCollector),
     /// Intermediate stats result.
-    Stats(IntermediateStats),
+    #[n(1)]
+    Stats(#[n(0)] IntermediateStats),
 }
 
 impl Default for IntermediateMetricResult {
@@ -26,9 +39,10 @@ impl Default for IntermediateMetricResult {
     }
 }
 
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 /// The percentiles collector used during segment collection and for merging results.
 pub struct PercentilesCollector {
+    #[n(0)]
     buckets: Vec<u64>, //sketch: sketches_ddsketch::DDSketch,
 }
 
@@ -38,6 +52,7 @@ impl Debug for PercentilesCollector {
     }
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for PercentilesCollector {
     fn default() -> Self {
         Self {
@@ -49,50 +64,66 @@ impl Default for PercentilesCollector {
 
 /// The intermediate bucket results. Internally they can be easily merged via the keys of the
 /// buckets.
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub enum IntermediateBucketResult {
     /// This is the histogram entry for a bucket, which contains a key, count, and optionally
     /// sub_aggregations.
+    #[n(0)]
     HistogramVec {
         /// The column_type of the underlying `Column`
+        #[n(0)]
         column_type: Option<ColumnType>,
         /// The buckets
+        #[n(1)]
         buckets: Vec<IntermediateHistogramBucketEntry>,
     },
     /// This is the histogram entry for a bucket, which contains a key, count, and optionally
     /// sub_aggregations.
+    #[n(1)]
     HistogramKeyed {
         /// The column_type of the underlying `Column`
+        #[n(0)]
         column_type: Option<ColumnType>,
         /// The buckets
+        #[n(1)]
         buckets: HashMap<u64, IntermediateHistogramBucketEntry>,
     },
 }
 
 /// This is the histogram entry for a bucket, which contains a key, count, and optionally
 /// sub_aggregations.
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub struct IntermediateHistogramBucketEntry {
     /// The unique the bucket is identified.
+    #[n(0)]
     pub key: f64,
     /// The number of documents in the bucket.
+    #[n(1)]
     pub doc_count: u64,
     /// The sub_aggregation in this bucket.
+    #[n(2)]
     pub sub_aggregation: IntermediateAggregationResults,
 }
 
 /// Contains the intermediate aggregation result, which is optimized to be merged with other
 /// intermediate results.
-#[derive(Debug, PartialEq, Default, Clone, Serialize, Deserialize, Readable, Writable)]
+#[derive(
+    Debug, PartialEq, Default, Clone, Serialize, Deserialize, Readable, Writable, Encode, Decode,
+)]
 pub struct IntermediateAggregationResults {
+    #[n(0)]
     pub(crate) metrics: Option<VecWithNames<IntermediateMetricResult>>,
+    #[n(1)]
     pub(crate) buckets: Option<VecWithNames<IntermediateBucketResult>>,
 }
+impl_formats!(IntermediateAggregationResults: serde, Minicbor, Speedy);
 
 /// Represents an associative array `(key => values)` in a very efficient manner.
-#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub(crate) struct VecWithNames<T: Clone + Debug> {
+    #[n(0)]
     pub(crate) values: Vec<T>,
+    #[n(1)]
     pub(crate) keys: Vec<String>,
 }
 
@@ -107,15 +138,19 @@ impl<T: Clone + Debug> Default for VecWithNames<T> {
 
 /// Intermediate result of the stats aggregation that can be combined with other intermediate
 /// results.
-#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub struct IntermediateStats {
     /// The number of extracted values.
+    #[n(0)]
     count: u64,
     /// The sum of the extracted values.
+    #[n(1)]
     sum: f64,
     /// The min value.
+    #[n(2)]
     min: f64,
     /// The max value.
+    #[n(3)]
     max: f64,
 }
 
@@ -156,19 +191,31 @@ impl Default for IntermediateStats {
     Deserialize,
     Readable,
     Writable,
+    Encode,
+    Decode,
 )]
+#[cbor(index_only)]
 #[repr(u8)]
 pub enum ColumnType {
+    #[n(0)]
     I64 = 0u8,
+    #[n(1)]
     U64 = 1u8,
+    #[n(2)]
     F64 = 2u8,
+    #[n(3)]
     Bytes = 3u8,
+    #[n(4)]
     Str = 4u8,
+    #[n(5)]
     Bool = 5u8,
+    #[n(6)]
     IpAddr = 6u8,
+    #[n(7)]
     DateTime = 7u8,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for ColumnType {
     fn default() -> Self {
         Self::F64
@@ -238,3 +285,266 @@ pub fn get_leaf() -> IntermediateAggregationResults {
         buckets: Some(buckets),
     }
 }
+
+/// Like [`get_test_struct`], but with `num_buckets` histogram buckets of realistic, seeded values
+/// instead of identical ones.
+pub fn generate_histogram(num_buckets: usize, seed: u64) -> IntermediateAggregationResults {
+    let mut rng = distributions::seeded_rng(seed);
+    let mut metrics = VecWithNames::default();
+    let mut buckets = VecWithNames::default();
+
+    // A sketch has at most 2048 bins, no matter how much data it has seen.
+    metrics.keys.push("percentiles".to_owned());
+    let percentile_buckets: Vec<u64> = (0..num_buckets.min(2048))
+        .map(|_| distributions::doc_count(&mut rng))
+        .collect();
+    metrics.values.push(IntermediateMetricResult::Percentiles(
+        PercentilesCollector {
+            buckets: percentile_buckets,
+        },
+    ));
+
+    // Hourly date histogram with timestamps in microseconds, like the percentiles data.
+    let start = 1_674_277_200_000_000.0;
+    let interval = 3_600_000_000.0;
+    let histogram_buckets: Vec<IntermediateHistogramBucketEntry> = (0..num_buckets)
+        .map(|i| {
+            let doc_count = distributions::doc_count(&mut rng);
+            IntermediateHistogramBucketEntry {
+                key: start + i as f64 * interval,
+                doc_count,
+                sub_aggregation: generate_leaf(&mut rng, doc_count),
+            }
+        })
+        .collect();
+    let values: Vec<f64> = (0..100)
+        .map(|_| distributions::lognormal(&mut rng, 3.0, 1.0))
+        .collect();
+    metrics.keys.push("stats".to_owned());
+    metrics
+        .values
+        .push(IntermediateMetricResult::Stats(IntermediateStats {
+            count: histogram_buckets
+                .iter()
+                .map(|bucket| bucket.doc_count)
+                .sum(),
+            sum: values.iter().sum(),
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }));
+
+    buckets.keys.push("histogram".to_owned());
+    buckets.values.push(IntermediateBucketResult::HistogramVec {
+        column_type: Some(ColumnType::DateTime),
+        buckets: histogram_buckets,
+    });
+
+    IntermediateAggregationResults {
+        metrics: Some(metrics),
+        buckets: Some(buckets),
+    }
+}
+
+/// A stats metric and a histogram bucket per value, next to stats with the defaults of
+/// `f64::MIN` and `f64::MAX`.
+pub fn generate_float_edge_cases(values: &[f64]) -> IntermediateAggregationResults {
+    let mut metrics = VecWithNames::default();
+    let mut buckets = VecWithNames::default();
+
+    metrics.keys.push("stats_default".to_owned());
+    metrics
+        .values
+        .push(IntermediateMetricResult::Stats(IntermediateStats::default()));
+    for (i, &value) in values.iter().enumerate() {
+        metrics.keys.push(format!("stats_{i}"));
+        metrics
+            .values
+            .push(IntermediateMetricResult::Stats(IntermediateStats {
+                count: 1,
+                sum: value,
+                min: value,
+                max: value,
+            }));
+    }
+
+    buckets.keys.push("histogram".to_owned());
+    buckets.values.push(IntermediateBucketResult::HistogramVec {
+        column_type: Some(ColumnType::F64),
+        buckets: values
+            .iter()
+            .map(|&key| IntermediateHistogramBucketEntry {
+                key,
+                doc_count: 1,
+                sub_aggregation: Default::default(),
+            })
+            .collect(),
+    });
+
+    IntermediateAggregationResults {
+        metrics: Some(metrics),
+        buckets: Some(buckets),
+    }
+}
+
+/// A keyed histogram with a few buckets, which split up the documents of the parent bucket.
+fn generate_leaf(rng: &mut StdRng, parent_doc_count: u64) -> IntermediateAggregationResults {
+    let mut buckets = VecWithNames::default();
+
+    buckets.keys.push("bucket2".to_owned());
+    let entries = (0..rng.gen_range(1..=3))
+        .map(|_| {
+            let index = rng.gen_range(0..100u64);
+            let entry = IntermediateHistogramBucketEntry {
+                key: index as f64 * 10.0,
+                doc_count: rng.gen_range(1..=parent_doc_count),
+                sub_aggregation: Default::default(),
+            };
+            (index, entry)
+        })
+        .collect();
+    buckets
+        .values
+        .push(IntermediateBucketResult::HistogramKeyed {
+            column_type: Some(ColumnType::F64),
+            buckets: entries,
+        });
+
+    IntermediateAggregationResults {
+        metrics: None,
+        buckets: Some(buckets),
+    }
+}
+
+/// Random results for `--fuzz`, up to 3 levels of sub-aggregations deep. `floats` decides which
+/// float values show up, e.g. whether NaN does.
+pub fn arb_results(
+    floats: BoxedStrategy<f64>,
+) -> impl Strategy<Value = IntermediateAggregationResults> {
+    let metrics = option::of(arb_names(arb_metric(floats.clone()))).boxed();
+    let leaf = metrics
+        .clone()
+        .prop_map(|metrics| IntermediateAggregationResults {
+            metrics,
+            buckets: None,
+        });
+    leaf.prop_recursive(3, 32, 4, move |inner| {
+        let buckets = option::of(arb_names(arb_bucket(inner, floats.clone())));
+        (metrics.clone(), buckets)
+            .prop_map(|(metrics, buckets)| IntermediateAggregationResults { metrics, buckets })
+    })
+}
+
+impl SubAggregations for IntermediateAggregationResults {
+    fn sub_aggregations(&self) -> Vec<&Self> {
+        let mut sub_aggregations = Vec::new();
+        for bucket in self.buckets.iter().flat_map(|buckets| &buckets.values) {
+            match bucket {
+                IntermediateBucketResult::HistogramVec { buckets, .. } => {
+                    sub_aggregations.extend(buckets.iter().map(|entry| &entry.sub_aggregation));
+                }
+                IntermediateBucketResult::HistogramKeyed { buckets, .. } => {
+                    sub_aggregations.extend(buckets.values().map(|entry| &entry.sub_aggregation));
+                }
+            }
+        }
+        sub_aggregations
+    }
+}
+
+fn arb_names<T: Clone + Debug>(
+    values: impl Strategy<Value = T>,
+) -> impl Strategy<Value = VecWithNames<T>> {
+    collection::vec((any::<String>(), values), 0..4).prop_map(|entries| {
+        let (keys, values) = entries.into_iter().unzip();
+        VecWithNames { values, keys }
+    })
+}
+
+fn arb_metric(floats: BoxedStrategy<f64>) -> impl Strategy<Value = IntermediateMetricResult> {
+    let percentiles = collection::vec(any::<u64>(), 0..8).prop_map(|buckets| {
+        IntermediateMetricResult::Percentiles(PercentilesCollector { buckets })
+    });
+    let stats = (any::<u64>(), floats.clone(), floats.clone(), floats).prop_map(
+        |(count, sum, min, max)| {
+            IntermediateMetricResult::Stats(IntermediateStats {
+                count,
+                sum,
+                min,
+                max,
+            })
+        },
+    );
+    prop_oneof![percentiles, stats]
+}
+
+fn arb_bucket(
+    sub_aggregation: BoxedStrategy<IntermediateAggregationResults>,
+    floats: BoxedStrategy<f64>,
+) -> impl Strategy<Value = IntermediateBucketResult> {
+    let entry = (floats, any::<u64>(), sub_aggregation)
+        .prop_map(
+            |(key, doc_count, sub_aggregation)| IntermediateHistogramBucketEntry {
+                key,
+                doc_count,
+                sub_aggregation,
+            },
+        )
+        .boxed();
+    let column_type = option::of(select(vec![
+        ColumnType::I64,
+        ColumnType::U64,
+        ColumnType::F64,
+        ColumnType::Bytes,
+        ColumnType::Str,
+        ColumnType::Bool,
+        ColumnType::IpAddr,
+        ColumnType::DateTime,
+    ]))
+    .boxed();
+    let vec = (column_type.clone(), collection::vec(entry.clone(), 0..4)).prop_map(
+        |(column_type, buckets)| IntermediateBucketResult::HistogramVec {
+            column_type,
+            buckets,
+        },
+    );
+    let keyed = (column_type, collection::hash_map(any::<u64>(), entry, 0..4)).prop_map(
+        |(column_type, buckets)| IntermediateBucketResult::HistogramKeyed {
+            column_type,
+            buckets,
+        },
+    );
+    prop_oneof![vec, keyed]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram_buckets(
+        results: &IntermediateAggregationResults,
+    ) -> &[IntermediateHistogramBucketEntry] {
+        match &results.buckets.as_ref().unwrap().values[..] {
+            [IntermediateBucketResult::HistogramVec { buckets, .. }] => buckets,
+            buckets => panic!("expected one histogram, got {buckets:?}"),
+        }
+    }
+
+    #[test]
+    fn generated_histograms_are_seeded() {
+        assert_eq!(generate_histogram(100, 1), generate_histogram(100, 1));
+        assert_ne!(generate_histogram(100, 1), generate_histogram(100, 2));
+    }
+
+    #[test]
+    fn generated_histograms_have_the_bucket_count() {
+        for num_buckets in [0, 1, 10, 3000] {
+            let results = generate_histogram(num_buckets, distributions::SEED);
+            assert_eq!(histogram_buckets(&results).len(), num_buckets);
+            let metrics = &results.metrics.as_ref().unwrap().values;
+            let IntermediateMetricResult::Percentiles(percentiles) = &metrics[0] else {
+                panic!("expected percentiles, got {:?}", metrics[0]);
+            };
+            assert_eq!(percentiles.buckets.len(), num_buckets.min(2048));
+        }
+    }
+}
@@ -1,14 +1,26 @@
 use fxhash::FxHashMap;
+use minicbor::{Decode, Encode};
+use proptest::{
+    collection, option,
+    prelude::{any, BoxedStrategy, Strategy},
+    prop_oneof,
+    sample::select,
+};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
 use std::fmt::Debug;
 
+use crate::{distributions, formats::impl_formats, fuzz::SubAggregations};
+
 /// Contains the intermediate aggregation result, which is optimized to be merged with other
 /// intermediate results.
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub struct IntermediateAggregationResults2 {
+    #[n(0)]
     pub(crate) aggs_res: VecWithNames<IntermediateAggregationResult>,
 }
+impl_formats!(IntermediateAggregationResults2: serde, Minicbor, Speedy);
 impl Debug for IntermediateAggregationResults2 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("IntermediateAggregationResults2").finish()
@@ -16,55 +28,67 @@ impl Debug for IntermediateAggregationResults2 {
 }
 
 /// Represents an associative array `(key => values)` in a very efficient manner.
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub(crate) struct VecWithNames<T: Clone> {
+    #[n(0)]
     pub(crate) values: Vec<T>,
+    #[n(1)]
     keys: Vec<String>,
 }
 
 /// An aggregation is either a bucket or a metric.
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub enum IntermediateAggregationResult {
     /// Bucket variant
-    Bucket(IntermediateBucketResult),
+    #[n(0)]
+    Bucket(#[n(0)] IntermediateBucketResult),
     /// Metric variant
-    Metric(IntermediateMetricResult),
+    #[n(1)]
+    Metric(#[n(0)] IntermediateMetricResult),
 }
 /// Holds the intermediate data for metric results
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub enum IntermediateMetricResult {
     /// Intermediate average result.
-    Percentiles(PercentilesCollector),
+    #[n(0)]
+    Percentiles(#[n(0)] PercentilesCollector),
 }
 
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 /// The percentiles collector used during segment collection and for merging results.
 pub struct PercentilesCollector {
+    #[n(0)]
     sketch: Sketch,
 }
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 /// The percentiles collector used during segment collection and for merging results.
 pub struct Sketch {
+    #[n(0)]
     store: Store,
 }
-#[derive(Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 /// The percentiles collector used during segment collection and for merging results.
 pub struct Store {
+    #[n(0)]
     bins: Vec<u64>,
 }
 
 /// The intermediate bucket results. Internally they can be easily merged via the keys of the
 /// buckets.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub enum IntermediateBucketResult {
     /// Term aggregation
-    Terms(IntermediateTermBucketResult),
+    #[n(0)]
+    Terms(#[n(0)] IntermediateTermBucketResult),
     /// This is the histogram entry for a bucket, which contains a key, count, and optionally
     /// sub_aggregations.
+    #[n(1)]
     Histogram {
         /// The column_type of the underlying `Column`
+        #[n(0)]
         column_type: Option<ColumnType>,
         /// The buckets
+        #[n(1)]
         buckets: Vec<IntermediateHistogramBucketEntry>,
     },
 }
@@ -84,45 +108,66 @@ pub enum IntermediateBucketResult {
     Deserialize,
     Readable,
     Writable,
+    Encode,
+    Decode,
 )]
+#[cbor(index_only)]
 #[repr(u8)]
 pub enum ColumnType {
+    #[n(0)]
     I64 = 0u8,
+    #[n(1)]
     U64 = 1u8,
+    #[n(2)]
     F64 = 2u8,
+    #[n(3)]
     Bytes = 3u8,
+    #[n(4)]
     Str = 4u8,
+    #[n(5)]
     Bool = 5u8,
+    #[n(6)]
     IpAddr = 6u8,
+    #[n(7)]
     DateTime = 7u8,
 }
 
 /// This is the histogram entry for a bucket, which contains a key, count, and optionally
 /// sub_aggregations.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub struct IntermediateHistogramBucketEntry {
     /// The unique the bucket is identified.
+    #[n(0)]
     pub key: f64,
     /// The number of documents in the bucket.
+    #[n(1)]
     pub doc_count: u64,
     /// The sub_aggregation in this bucket.
+    #[n(2)]
     pub sub_aggregation: IntermediateAggregationResults2,
 }
 
-#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(
+    Default, Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode,
+)]
 /// Term aggregation including error counts
 pub struct IntermediateTermBucketResult {
+    #[n(0)]
     pub(crate) entries: FxHashMap<String, IntermediateTermBucketEntry>,
+    #[n(1)]
     pub(crate) sum_other_doc_count: u64,
+    #[n(2)]
     pub(crate) doc_count_error_upper_bound: u64,
 }
-#[derive(Clone, Debug, Serialize, Deserialize, PartialOrd, Readable, Writable)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialOrd, Readable, Writable, Encode, Decode)]
 /// The key to identify a bucket.
 pub enum Key {
     /// String key
-    Str(String),
+    #[n(0)]
+    Str(#[n(0)] String),
     /// `f64` key
-    F64(f64),
+    #[n(1)]
+    F64(#[n(0)] f64),
 }
 impl Eq for Key {}
 impl std::hash::Hash for Key {
@@ -147,10 +192,289 @@ impl PartialEq for Key {
 
 /// This is the term entry for a bucket, which contains a count, and optionally
 /// sub_aggregations.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
 pub struct IntermediateTermBucketEntry {
     /// The number of documents in the bucket.
+    #[n(0)]
     pub doc_count: u64,
     /// The sub_aggregation in this bucket.
+    #[n(1)]
     pub sub_aggregation: IntermediateAggregationResults2,
 }
+
+/// A term aggregation with `num_buckets` seeded terms, shaped like the term aggregation data.
+pub fn generate_terms(num_buckets: usize, seed: u64) -> IntermediateAggregationResults2 {
+    let mut rng = distributions::seeded_rng(seed);
+    let mut entries = FxHashMap::default();
+    while entries.len() < num_buckets {
+        let entry = IntermediateTermBucketEntry {
+            doc_count: distributions::doc_count(&mut rng),
+            sub_aggregation: IntermediateAggregationResults2 {
+                aggs_res: VecWithNames {
+                    values: Vec::new(),
+                    keys: Vec::new(),
+                },
+            },
+        };
+        entries.insert(distributions::term(&mut rng), entry);
+    }
+    let terms = IntermediateTermBucketResult {
+        entries,
+        sum_other_doc_count: distributions::doc_count(&mut rng),
+        doc_count_error_upper_bound: rng.gen_range(0..100),
+    };
+    IntermediateAggregationResults2 {
+        aggs_res: VecWithNames {
+            values: vec![IntermediateAggregationResult::Bucket(
+                IntermediateBucketResult::Terms(terms),
+            )],
+            keys: vec!["terms".to_owned()],
+        },
+    }
+}
+
+/// A term aggregation with a single bucket for `term`, without sub-aggregations.
+pub fn single_term(term: String) -> IntermediateTermBucketResult {
+    let entry = IntermediateTermBucketEntry {
+        doc_count: 1,
+        sub_aggregation: IntermediateAggregationResults2 {
+            aggs_res: VecWithNames {
+                values: Vec::new(),
+                keys: Vec::new(),
+            },
+        },
+    };
+    IntermediateTermBucketResult {
+        entries: FxHashMap::from_iter([(term, entry)]),
+        sum_other_doc_count: 0,
+        doc_count_error_upper_bound: 0,
+    }
+}
+
+/// Sub-aggregation trees `depth` levels deep, every aggregation has `fan_out` buckets. The levels
+/// alternate between term and histogram aggregations, so both recursive paths are covered.
+pub fn generate_nested(depth: usize, fan_out: usize, seed: u64) -> IntermediateAggregationResults2 {
+    let mut rng = distributions::seeded_rng(seed);
+    let mut tree = IntermediateAggregationResults2 {
+        aggs_res: VecWithNames {
+            values: Vec::new(),
+            keys: Vec::new(),
+        },
+    };
+    // Built bottom up, a recursive generator would overflow the stack before any format does.
+    for level in 0..depth {
+        let mut subtrees = vec![tree];
+        while subtrees.len() < fan_out {
+            subtrees.push(subtrees[0].clone());
+        }
+        let (key, bucket) = if level % 2 == 0 {
+            let entries = subtrees
+                .into_iter()
+                .map(|sub_aggregation| {
+                    let entry = IntermediateTermBucketEntry {
+                        doc_count: distributions::doc_count(&mut rng),
+                        sub_aggregation,
+                    };
+                    (distributions::term(&mut rng), entry)
+                })
+                .collect();
+            let terms = IntermediateTermBucketResult {
+                entries,
+                sum_other_doc_count: distributions::doc_count(&mut rng),
+                doc_count_error_upper_bound: rng.gen_range(0..100),
+            };
+            ("terms", IntermediateBucketResult::Terms(terms))
+        } else {
+            let buckets = subtrees
+                .into_iter()
+                .enumerate()
+                .map(|(i, sub_aggregation)| IntermediateHistogramBucketEntry {
+                    key: i as f64 * 10.0,
+                    doc_count: distributions::doc_count(&mut rng),
+                    sub_aggregation,
+                })
+                .collect();
+            let histogram = IntermediateBucketResult::Histogram {
+                column_type: Some(ColumnType::F64),
+                buckets,
+            };
+            ("histogram", histogram)
+        };
+        tree = IntermediateAggregationResults2 {
+            aggs_res: VecWithNames {
+                values: vec![IntermediateAggregationResult::Bucket(bucket)],
+                keys: vec![format!("{key}_{level}")],
+            },
+        };
+    }
+    tree
+}
+
+/// Random results for `--fuzz`, like [`crate::test_struct_agg1::arb_results`].
+pub fn arb_results(
+    floats: BoxedStrategy<f64>,
+) -> impl Strategy<Value = IntermediateAggregationResults2> {
+    let leaf = arb_names(arb_metric().prop_map(IntermediateAggregationResult::Metric));
+    leaf.prop_map(|aggs_res| IntermediateAggregationResults2 { aggs_res })
+        .prop_recursive(3, 32, 4, move |inner| {
+            let aggregation = prop_oneof![
+                arb_metric().prop_map(IntermediateAggregationResult::Metric),
+                arb_bucket(inner, floats.clone()).prop_map(IntermediateAggregationResult::Bucket),
+            ];
+            arb_names(aggregation).prop_map(|aggs_res| IntermediateAggregationResults2 { aggs_res })
+        })
+}
+
+impl SubAggregations for IntermediateAggregationResults2 {
+    fn sub_aggregations(&self) -> Vec<&Self> {
+        let mut sub_aggregations = Vec::new();
+        for aggregation in &self.aggs_res.values {
+            match aggregation {
+                IntermediateAggregationResult::Bucket(IntermediateBucketResult::Terms(terms)) => {
+                    sub_aggregations
+                        .extend(terms.entries.values().map(|entry| &entry.sub_aggregation));
+                }
+                IntermediateAggregationResult::Bucket(IntermediateBucketResult::Histogram {
+                    buckets,
+                    ..
+                }) => sub_aggregations.extend(buckets.iter().map(|entry| &entry.sub_aggregation)),
+                IntermediateAggregationResult::Metric(_) => {}
+            }
+        }
+        sub_aggregations
+    }
+}
+
+fn arb_names<T: Clone + Debug>(
+    values: impl Strategy<Value = T>,
+) -> impl Strategy<Value = VecWithNames<T>> {
+    collection::vec((any::<String>(), values), 0..4).prop_map(|entries| {
+        let (keys, values) = entries.into_iter().unzip();
+        VecWithNames { values, keys }
+    })
+}
+
+fn arb_metric() -> impl Strategy<Value = IntermediateMetricResult> {
+    collection::vec(any::<u64>(), 0..8).prop_map(|bins| {
+        IntermediateMetricResult::Percentiles(PercentilesCollector {
+            sketch: Sketch {
+                store: Store { bins },
+            },
+        })
+    })
+}
+
+fn arb_bucket(
+    sub_aggregation: BoxedStrategy<IntermediateAggregationResults2>,
+    floats: BoxedStrategy<f64>,
+) -> impl Strategy<Value = IntermediateBucketResult> {
+    let term_entry =
+        (any::<u64>(), sub_aggregation.clone()).prop_map(|(doc_count, sub_aggregation)| {
+            IntermediateTermBucketEntry {
+                doc_count,
+                sub_aggregation,
+            }
+        });
+    let terms = (
+        collection::hash_map(any::<String>(), term_entry, 0..4),
+        any::<u64>(),
+        any::<u64>(),
+    )
+        .prop_map(
+            |(entries, sum_other_doc_count, doc_count_error_upper_bound)| {
+                IntermediateBucketResult::Terms(IntermediateTermBucketResult {
+                    entries: entries.into_iter().collect(),
+                    sum_other_doc_count,
+                    doc_count_error_upper_bound,
+                })
+            },
+        );
+    let histogram_entry =
+        (floats, any::<u64>(), sub_aggregation).prop_map(|(key, doc_count, sub_aggregation)| {
+            IntermediateHistogramBucketEntry {
+                key,
+                doc_count,
+                sub_aggregation,
+            }
+        });
+    let column_type = option::of(select(vec![
+        ColumnType::I64,
+        ColumnType::U64,
+        ColumnType::F64,
+        ColumnType::Bytes,
+        ColumnType::Str,
+        ColumnType::Bool,
+        ColumnType::IpAddr,
+        ColumnType::DateTime,
+    ]));
+    let histogram =
+        (column_type, collection::vec(histogram_entry, 0..4)).prop_map(|(column_type, buckets)| {
+            IntermediateBucketResult::Histogram {
+                column_type,
+                buckets,
+            }
+        });
+    prop_oneof![terms, histogram]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_terms_are_seeded() {
+        assert_eq!(generate_terms(100, 1), generate_terms(100, 1));
+        assert_ne!(generate_terms(100, 1), generate_terms(100, 2));
+    }
+
+    #[test]
+    fn generated_terms_have_the_bucket_count() {
+        for num_buckets in [0, 1, 10, 1000] {
+            let results = generate_terms(num_buckets, distributions::SEED);
+            let [IntermediateAggregationResult::Bucket(IntermediateBucketResult::Terms(terms))] =
+                &results.aggs_res.values[..]
+            else {
+                panic!("expected one term aggregation, got {results:?}");
+            };
+            assert_eq!(terms.entries.len(), num_buckets);
+        }
+    }
+
+    /// The depth of `tree` and its number of leaves, checking that every aggregation has
+    /// `fan_out` buckets.
+    fn shape(tree: &IntermediateAggregationResults2, fan_out: usize) -> (usize, usize) {
+        let sub_aggregations = tree.sub_aggregations();
+        if sub_aggregations.is_empty() {
+            return (0, 1);
+        }
+        assert_eq!(sub_aggregations.len(), fan_out);
+        let shapes: Vec<_> = sub_aggregations
+            .into_iter()
+            .map(|sub| shape(sub, fan_out))
+            .collect();
+        assert!(shapes.iter().all(|&(depth, _)| depth == shapes[0].0));
+        (
+            shapes[0].0 + 1,
+            shapes.iter().map(|(_, leaves)| leaves).sum(),
+        )
+    }
+
+    #[test]
+    fn nested_trees_have_the_depth_and_fan_out() {
+        for (depth, fan_out) in [(0, 3), (1, 1), (1, 4), (5, 3), (6, 2)] {
+            let tree = generate_nested(depth, fan_out, distributions::SEED);
+            assert_eq!(shape(&tree, fan_out), (depth, fan_out.pow(depth as u32)));
+        }
+    }
+
+    #[test]
+    fn nested_levels_alternate_between_terms_and_histograms() {
+        let mut tree = &generate_nested(4, 2, distributions::SEED);
+        for level in (0..4).rev() {
+            let expected = if level % 2 == 0 { "terms" } else { "histogram" };
+            assert_eq!(tree.aggs_res.keys, [format!("{expected}_{level}")]);
+            tree = tree.sub_aggregations()[0];
+        }
+        assert!(tree.aggs_res.values.is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+//! Small types for the serde feature matrix, one per feature.
+//!
+//! Where Speedy and Minicbor have an equivalent of the feature, the types derive their traits as
+//! well. Serde attributes like tagged enums or `flatten` have none, those types skip both.
+
+use std::collections::BTreeMap;
+
+use minicbor::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+
+use crate::formats::impl_formats;
+
+// The features are tested on vectors of the enums, to cover all variants at once.
+impl_formats!(Vec<InternallyTagged>: serde);
+impl_formats!(Vec<AdjacentlyTagged>: serde);
+impl_formats!(Vec<Untagged>: serde);
+impl_formats!(Flattened: serde);
+impl_formats!(Vec<SkipIf>: serde);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Inner {
+    pub a: u32,
+    pub b: String,
+}
+
+/// `#[serde(tag = "type")]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum InternallyTagged {
+    Unit,
+    Newtype(Inner),
+    Struct { a: u32, b: String },
+}
+
+/// `#[serde(tag = "t", content = "c")]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", content = "c")]
+pub enum AdjacentlyTagged {
+    Unit,
+    Newtype(u32),
+    Tuple(u32, String),
+    Struct { a: u32 },
+}
+
+/// `#[serde(untagged)]`, needs `deserialize_any` to find the variant.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Untagged {
+    Int(u64),
+    Text(String),
+    Struct { a: u32, b: bool },
+}
+
+/// `#[serde(flatten)]`, serialized as a map.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Flattened {
+    pub id: u32,
+    #[serde(flatten)]
+    pub inner: Inner,
+}
+
+/// `skip_serializing_if`, a struct whose number of fields depends on the value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SkipIf {
+    pub a: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub b: Option<u32>,
+    pub c: u32,
+}
+
+impl_formats!(Before => After: serde, Minicbor, Speedy);
+
+/// Written by an older version, read back as [`After`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct Before {
+    #[n(0)]
+    pub a: u32,
+}
+
+/// [`Before`] with a field added with `#[serde(default)]`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct After {
+    #[n(0)]
+    pub a: u32,
+    #[n(1)]
+    #[serde(default = "default_b")]
+    pub b: u32,
+}
+
+pub fn default_b() -> u32 {
+    42
+}
+
+/// Minicbor 0.19 has no 128 bit integers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable)]
+pub struct Wide {
+    pub unsigned: u128,
+    pub signed: i128,
+}
+impl_formats!(Wide: serde, Speedy);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct Chars {
+    #[n(0)]
+    pub chars: Vec<char>,
+}
+impl_formats!(Chars: serde, Minicbor, Speedy);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct NestedOption {
+    #[n(0)]
+    pub values: Vec<Option<Option<u32>>>,
+}
+impl_formats!(NestedOption: serde, Minicbor, Speedy);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct UnitStruct;
+impl_formats!(UnitStruct: serde, Minicbor, Speedy);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct NewtypeStruct(#[n(0)] pub u32);
+impl_formats!(NewtypeStruct: serde, Minicbor, Speedy);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct TupleStruct(#[n(0)] pub u32, #[n(1)] pub String, #[n(2)] pub bool);
+impl_formats!(TupleStruct: serde, Minicbor, Speedy);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct IntKeys {
+    #[n(0)]
+    pub map: BTreeMap<i64, u32>,
+}
+impl_formats!(IntKeys: serde, Minicbor, Speedy);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct TupleKeys {
+    #[n(0)]
+    pub map: BTreeMap<(u32, u32), u32>,
+}
+impl_formats!(TupleKeys: serde, Minicbor, Speedy);
+
+/// Goes through `serialize_bytes` instead of a sequence of `u8`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct Bytes {
+    #[n(0)]
+    #[serde(with = "serde_bytes")]
+    #[cbor(with = "minicbor::bytes")]
+    pub data: Vec<u8>,
+}
+impl_formats!(Bytes: serde, Minicbor, Speedy);
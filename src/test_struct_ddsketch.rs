@@ -0,0 +1,73 @@
+use std::fmt;
+
+use rand::{rngs::StdRng, Rng};
+use serde::{Deserialize, Serialize};
+use sketches_ddsketch::{Config, DDSketch};
+
+use crate::{cli::FloatCmp, diff::Diff, distributions, formats::impl_formats};
+
+/// Fixed seed, so every run serializes the same sketches.
+const SEED: u64 = 0xdd5c;
+
+/// Values added to each sketch.
+const NUM_VALUES: usize = 100_000;
+
+/// What the values of a sketch are drawn from, the shape decides how many bins are used.
+#[derive(Debug, Clone, Copy)]
+pub enum Distribution {
+    /// Uniform in `[0, 1000)`.
+    Uniform,
+    /// Latency like, median around 20.
+    LogNormal,
+    /// Heavy tailed, `x_m = 1`, `alpha = 1.5`.
+    Pareto,
+}
+
+impl Distribution {
+    fn sample(self, rng: &mut StdRng) -> f64 {
+        match self {
+            Distribution::Uniform => rng.gen_range(0.0..1000.0),
+            Distribution::LogNormal => distributions::lognormal(rng, 3.0, 1.0),
+            Distribution::Pareto => distributions::pareto(rng, 1.0, 1.5),
+        }
+    }
+}
+
+/// A `DDSketch` as sent between nodes for percentile aggregations.
+///
+/// Only serde is supported, Speedy and Minicbor are skipped.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Sketch(pub DDSketch);
+
+impl Sketch {
+    pub fn from_distribution(distribution: Distribution) -> Self {
+        let mut rng = distributions::seeded_rng(SEED);
+        let mut sketch = DDSketch::new(Config::defaults());
+        for _ in 0..NUM_VALUES {
+            sketch.add(distribution.sample(&mut rng));
+        }
+        Sketch(sketch)
+    }
+}
+
+impl fmt::Debug for Sketch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sketch")
+            .field("count", &self.0.count())
+            .field("min", &self.0.min())
+            .field("max", &self.0.max())
+            .field("sum", &self.0.sum())
+            .finish()
+    }
+}
+
+/// `DDSketch` has no `PartialEq` and private fields, which are compared through its serde fields
+/// as [`Diff`] sees them. No benchmarked format is involved, so none can hide its own bugs.
+impl PartialEq for Sketch {
+    fn eq(&self, other: &Self) -> bool {
+        self.diff(other, FloatCmp::PartialEq).is_empty()
+    }
+}
+
+impl_formats!(Sketch: serde);
@@ -0,0 +1,50 @@
+//! Float values that text formats may not roundtrip and `PartialEq` misjudges, in the float
+//! fields of both aggregation result types.
+
+use minicbor::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+
+use crate::{
+    formats::impl_formats,
+    test_struct_agg1::{generate_float_edge_cases, IntermediateAggregationResults},
+    test_struct_agg2::Key,
+};
+
+/// NaN with a payload and with the sign bit set, the signed zeros and infinities, subnormals,
+/// and the extremes of the normal range.
+pub const EDGE_CASES: [f64; 15] = [
+    f64::NAN,
+    -f64::NAN,
+    f64::from_bits(0x7ff8_0000_0000_0001),
+    f64::INFINITY,
+    f64::NEG_INFINITY,
+    0.0,
+    -0.0,
+    f64::from_bits(1),
+    -f64::from_bits(1),
+    f64::from_bits(0x000f_ffff_ffff_ffff),
+    f64::MIN_POSITIVE,
+    f64::EPSILON,
+    0.1,
+    f64::MAX,
+    f64::MIN,
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct FloatEdgeCases {
+    /// Stats and histogram keys.
+    #[n(0)]
+    pub aggregations: IntermediateAggregationResults,
+    /// Term keys.
+    #[n(1)]
+    pub keys: Vec<Key>,
+}
+impl_formats!(FloatEdgeCases: serde, Minicbor, Speedy);
+
+pub fn float_edge_cases() -> FloatEdgeCases {
+    FloatEdgeCases {
+        aggregations: generate_float_edge_cases(&EDGE_CASES),
+        keys: EDGE_CASES.iter().map(|&value| Key::F64(value)).collect(),
+    }
+}
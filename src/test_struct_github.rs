@@ -0,0 +1,407 @@
+use minicbor::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use speedy::{Readable, Writable};
+
+use crate::formats::impl_formats;
+
+/// An event of the GitHub events API. Only pull request events are modeled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct GithubEvent {
+    #[n(0)]
+    pub id: String,
+    #[serde(rename = "type")]
+    #[n(1)]
+    pub kind: String,
+    #[n(2)]
+    pub actor: Actor,
+    #[n(3)]
+    pub repo: EventRepo,
+    #[n(4)]
+    pub payload: PullRequestEventPayload,
+    #[n(5)]
+    pub public: bool,
+    /// RFC 3339 timestamp.
+    #[n(6)]
+    pub created_at: String,
+    #[n(7)]
+    pub org: Option<Actor>,
+}
+// Scenarios run on all loaded events at once.
+impl_formats!(Vec<GithubEvent>: serde, Minicbor, Speedy);
+
+/// The user or organization in the event header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct Actor {
+    #[n(0)]
+    pub id: u64,
+    #[n(1)]
+    pub login: String,
+    #[n(2)]
+    pub gravatar_id: String,
+    #[n(3)]
+    pub url: String,
+    #[n(4)]
+    pub avatar_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct EventRepo {
+    #[n(0)]
+    pub id: u64,
+    #[n(1)]
+    pub name: String,
+    #[n(2)]
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct PullRequestEventPayload {
+    #[n(0)]
+    pub action: String,
+    #[n(1)]
+    pub number: u64,
+    #[n(2)]
+    pub pull_request: PullRequest,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct PullRequest {
+    #[n(0)]
+    pub url: String,
+    #[n(1)]
+    pub id: u64,
+    #[n(2)]
+    pub html_url: String,
+    #[n(3)]
+    pub diff_url: String,
+    #[n(4)]
+    pub patch_url: String,
+    #[n(5)]
+    pub issue_url: String,
+    #[n(6)]
+    pub number: u64,
+    #[n(7)]
+    pub state: String,
+    #[n(8)]
+    pub locked: bool,
+    #[n(9)]
+    pub title: String,
+    #[n(10)]
+    pub user: User,
+    #[n(11)]
+    pub body: Option<String>,
+    #[n(12)]
+    pub created_at: String,
+    #[n(13)]
+    pub updated_at: String,
+    #[n(14)]
+    pub closed_at: Option<String>,
+    #[n(15)]
+    pub merged_at: Option<String>,
+    #[n(16)]
+    pub merge_commit_sha: Option<String>,
+    #[n(17)]
+    pub assignee: Option<User>,
+    #[n(18)]
+    pub milestone: Option<Milestone>,
+    #[n(19)]
+    pub commits_url: String,
+    #[n(20)]
+    pub review_comments_url: String,
+    #[n(21)]
+    pub review_comment_url: String,
+    #[n(22)]
+    pub comments_url: String,
+    #[n(23)]
+    pub statuses_url: String,
+    #[n(24)]
+    pub head: GitRef,
+    #[n(25)]
+    pub base: GitRef,
+    #[serde(rename = "_links")]
+    #[n(26)]
+    pub links: PullRequestLinks,
+    #[n(27)]
+    pub merged: bool,
+    #[n(28)]
+    pub mergeable: Option<bool>,
+    #[n(29)]
+    pub mergeable_state: String,
+    #[n(30)]
+    pub merged_by: Option<User>,
+    #[n(31)]
+    pub comments: u64,
+    #[n(32)]
+    pub review_comments: u64,
+    #[n(33)]
+    pub commits: u64,
+    #[n(34)]
+    pub additions: u64,
+    #[n(35)]
+    pub deletions: u64,
+    #[n(36)]
+    pub changed_files: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct User {
+    #[n(0)]
+    pub login: String,
+    #[n(1)]
+    pub id: u64,
+    #[n(2)]
+    pub avatar_url: String,
+    #[n(3)]
+    pub gravatar_id: String,
+    #[n(4)]
+    pub url: String,
+    #[n(5)]
+    pub html_url: String,
+    #[n(6)]
+    pub followers_url: String,
+    #[n(7)]
+    pub following_url: String,
+    #[n(8)]
+    pub gists_url: String,
+    #[n(9)]
+    pub starred_url: String,
+    #[n(10)]
+    pub subscriptions_url: String,
+    #[n(11)]
+    pub organizations_url: String,
+    #[n(12)]
+    pub repos_url: String,
+    #[n(13)]
+    pub events_url: String,
+    #[n(14)]
+    pub received_events_url: String,
+    #[serde(rename = "type")]
+    #[n(15)]
+    pub kind: String,
+    #[n(16)]
+    pub site_admin: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct Milestone {
+    #[n(0)]
+    pub url: String,
+    #[n(1)]
+    pub html_url: String,
+    #[n(2)]
+    pub id: u64,
+    #[n(3)]
+    pub number: u64,
+    #[n(4)]
+    pub title: String,
+    #[n(5)]
+    pub description: Option<String>,
+    #[n(6)]
+    pub creator: Option<User>,
+    #[n(7)]
+    pub open_issues: u64,
+    #[n(8)]
+    pub closed_issues: u64,
+    #[n(9)]
+    pub state: String,
+    #[n(10)]
+    pub created_at: String,
+    #[n(11)]
+    pub updated_at: Option<String>,
+    #[n(12)]
+    pub due_on: Option<String>,
+    #[n(13)]
+    pub closed_at: Option<String>,
+}
+
+/// The head or base of a pull request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct GitRef {
+    #[n(0)]
+    pub label: String,
+    #[serde(rename = "ref")]
+    #[n(1)]
+    pub git_ref: String,
+    #[n(2)]
+    pub sha: String,
+    #[n(3)]
+    pub user: User,
+    /// `None` if the repository was deleted.
+    #[n(4)]
+    pub repo: Option<Repository>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct Repository {
+    #[n(0)]
+    pub id: u64,
+    #[n(1)]
+    pub name: String,
+    #[n(2)]
+    pub full_name: String,
+    #[n(3)]
+    pub owner: User,
+    #[n(4)]
+    pub private: bool,
+    #[n(5)]
+    pub html_url: String,
+    #[n(6)]
+    pub description: Option<String>,
+    #[n(7)]
+    pub fork: bool,
+    #[n(8)]
+    pub url: String,
+    #[n(9)]
+    pub forks_url: String,
+    #[n(10)]
+    pub keys_url: String,
+    #[n(11)]
+    pub collaborators_url: String,
+    #[n(12)]
+    pub teams_url: String,
+    #[n(13)]
+    pub hooks_url: String,
+    #[n(14)]
+    pub issue_events_url: String,
+    #[n(15)]
+    pub events_url: String,
+    #[n(16)]
+    pub assignees_url: String,
+    #[n(17)]
+    pub branches_url: String,
+    #[n(18)]
+    pub tags_url: String,
+    #[n(19)]
+    pub blobs_url: String,
+    #[n(20)]
+    pub git_tags_url: String,
+    #[n(21)]
+    pub git_refs_url: String,
+    #[n(22)]
+    pub trees_url: String,
+    #[n(23)]
+    pub statuses_url: String,
+    #[n(24)]
+    pub languages_url: String,
+    #[n(25)]
+    pub stargazers_url: String,
+    #[n(26)]
+    pub contributors_url: String,
+    #[n(27)]
+    pub subscribers_url: String,
+    #[n(28)]
+    pub subscription_url: String,
+    #[n(29)]
+    pub commits_url: String,
+    #[n(30)]
+    pub git_commits_url: String,
+    #[n(31)]
+    pub comments_url: String,
+    #[n(32)]
+    pub issue_comment_url: String,
+    #[n(33)]
+    pub contents_url: String,
+    #[n(34)]
+    pub compare_url: String,
+    #[n(35)]
+    pub merges_url: String,
+    #[n(36)]
+    pub archive_url: String,
+    #[n(37)]
+    pub downloads_url: String,
+    #[n(38)]
+    pub issues_url: String,
+    #[n(39)]
+    pub pulls_url: String,
+    #[n(40)]
+    pub milestones_url: String,
+    #[n(41)]
+    pub notifications_url: String,
+    #[n(42)]
+    pub labels_url: String,
+    #[n(43)]
+    pub releases_url: String,
+    #[n(44)]
+    pub created_at: String,
+    #[n(45)]
+    pub updated_at: String,
+    #[n(46)]
+    pub pushed_at: Option<String>,
+    #[n(47)]
+    pub git_url: String,
+    #[n(48)]
+    pub ssh_url: String,
+    #[n(49)]
+    pub clone_url: String,
+    #[n(50)]
+    pub svn_url: String,
+    #[n(51)]
+    pub homepage: Option<String>,
+    #[n(52)]
+    pub size: u64,
+    #[n(53)]
+    pub stargazers_count: u64,
+    #[n(54)]
+    pub watchers_count: u64,
+    #[n(55)]
+    pub language: Option<String>,
+    #[n(56)]
+    pub has_issues: bool,
+    #[n(57)]
+    pub has_downloads: bool,
+    #[n(58)]
+    pub has_wiki: bool,
+    #[n(59)]
+    pub has_pages: bool,
+    #[n(60)]
+    pub forks_count: u64,
+    #[n(61)]
+    pub mirror_url: Option<String>,
+    #[n(62)]
+    pub open_issues_count: u64,
+    #[n(63)]
+    pub forks: u64,
+    #[n(64)]
+    pub open_issues: u64,
+    #[n(65)]
+    pub watchers: u64,
+    #[n(66)]
+    pub default_branch: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct PullRequestLinks {
+    #[serde(rename = "self")]
+    #[n(0)]
+    pub self_link: Link,
+    #[n(1)]
+    pub html: Link,
+    #[n(2)]
+    pub issue: Link,
+    #[n(3)]
+    pub comments: Link,
+    #[n(4)]
+    pub review_comments: Link,
+    #[n(5)]
+    pub review_comment: Link,
+    #[n(6)]
+    pub commits: Link,
+    #[n(7)]
+    pub statuses: Link,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Readable, Writable, Encode, Decode)]
+pub struct Link {
+    #[n(0)]
+    pub href: String,
+}
+
+/// Loads one event per line, as returned by the GitHub archive.
+pub fn load_events(path: &str) -> anyhow::Result<Vec<GithubEvent>> {
+    let content = std::fs::read_to_string(path)?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
@@ -0,0 +1,125 @@
+//! Types that serialize like the aggregation results, but with collections that claim far more
+//! elements than they have. Decoding them as the real types shows whether a format trusts the
+//! length prefix of its input.
+
+use minicbor::{encode, Encode, Encoder};
+use serde::{
+    ser::{SerializeMap, SerializeSeq},
+    Serialize, Serializer,
+};
+use speedy::{Context, Writable, Writer};
+
+use crate::{
+    formats::impl_formats,
+    test_struct_agg2::{IntermediateTermBucketEntry, IntermediateTermBucketResult, Store},
+};
+
+impl_formats!(HostileStore => Store: serde, Minicbor, Speedy);
+impl_formats!(HostileTerms => IntermediateTermBucketResult: serde, Minicbor, Speedy);
+// The string attack writes the real type and cuts it off.
+impl_formats!(IntermediateTermBucketResult: serde, Minicbor, Speedy);
+
+/// A sequence with the length prefix of `len` elements, followed by only `items`.
+///
+/// Formats with 32 bit length prefixes can't write longer claims, see
+/// [`crate::formats::Deser::max_claimed_len`]. Speedy claims at most `u32::MAX` elements.
+pub struct ClaimedSeq<T> {
+    pub len: u64,
+    pub items: Vec<T>,
+}
+
+impl<T: Serialize> Serialize for ClaimedSeq<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len as usize))?;
+        for item in &self.items {
+            seq.serialize_element(item)?;
+        }
+        seq.end()
+    }
+}
+
+impl<C: Context, T: Writable<C>> Writable<C> for ClaimedSeq<T> {
+    fn write_to<W: ?Sized + Writer<C>>(&self, writer: &mut W) -> Result<(), C::Error> {
+        writer.write_u32(self.len.min(u32::MAX.into()) as u32)?;
+        for item in &self.items {
+            item.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C, T: Encode<C>> Encode<C> for ClaimedSeq<T> {
+    fn encode<W: encode::Write>(
+        &self,
+        e: &mut Encoder<W>,
+        ctx: &mut C,
+    ) -> Result<(), encode::Error<W::Error>> {
+        e.array(self.len)?;
+        for item in &self.items {
+            item.encode(e, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+/// A map with the length prefix of `len` entries, followed by only `entries`.
+pub struct ClaimedMap<K, V> {
+    pub len: u64,
+    pub entries: Vec<(K, V)>,
+}
+
+impl<K: Serialize, V: Serialize> Serialize for ClaimedMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len as usize))?;
+        for (key, value) in &self.entries {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<C: Context, K: Writable<C>, V: Writable<C>> Writable<C> for ClaimedMap<K, V> {
+    fn write_to<W: ?Sized + Writer<C>>(&self, writer: &mut W) -> Result<(), C::Error> {
+        writer.write_u32(self.len.min(u32::MAX.into()) as u32)?;
+        for (key, value) in &self.entries {
+            key.write_to(writer)?;
+            value.write_to(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C, K: Encode<C>, V: Encode<C>> Encode<C> for ClaimedMap<K, V> {
+    fn encode<W: encode::Write>(
+        &self,
+        e: &mut Encoder<W>,
+        ctx: &mut C,
+    ) -> Result<(), encode::Error<W::Error>> {
+        e.map(self.len)?;
+        for (key, value) in &self.entries {
+            key.encode(e, ctx)?;
+            value.encode(e, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializes like [`crate::test_struct_agg2::Store`].
+#[derive(Serialize, Writable, Encode)]
+#[serde(rename = "Store")]
+pub struct HostileStore {
+    #[n(0)]
+    pub bins: ClaimedSeq<u64>,
+}
+
+/// Serializes like [`crate::test_struct_agg2::IntermediateTermBucketResult`].
+#[derive(Serialize, Writable, Encode)]
+#[serde(rename = "IntermediateTermBucketResult")]
+pub struct HostileTerms {
+    #[n(0)]
+    pub entries: ClaimedMap<String, IntermediateTermBucketEntry>,
+    #[n(1)]
+    pub sum_other_doc_count: u64,
+    #[n(2)]
+    pub doc_count_error_upper_bound: u64,
+}
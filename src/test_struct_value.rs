@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use crate::formats::impl_formats;
+
+/// A `serde_json::Value`, to test schemaless data that needs `deserialize_any`.
+///
+/// Speedy and Minicbor don't go through serde, and `Value` has no impls for them, so they are
+/// skipped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct JsonValue(pub serde_json::Value);
+
+impl_formats!(JsonValue: serde);